@@ -0,0 +1,169 @@
+//! Hiding the identities of the players in a log before publishing it. Every
+//! player is consistently replaced by a pseudonym like `Player1`, while NPCs
+//! keep their names, so the log can still be analysed.
+
+use crate::guid::{Guid, GuidType};
+use crate::log::Log;
+use crate::payload::{Payload, SuffixParams};
+use crate::unit::Unit;
+use crate::unit_table::UnitTable;
+use std::collections::{HashMap, HashSet};
+
+/// Replaces players by pseudonyms. The same seed always produces the same
+/// pseudonyms for the same log, while different seeds produce different ids.
+pub struct Anonymizer {
+    seed:    u64,
+    /// The pseudonyms of all players seen so far, by their original id.
+    players: HashMap<Guid, (Guid, String)>,
+    /// The pseudonyms by the original names of the players.
+    names:   HashMap<String, String>,
+    /// The ids that have been handed out, to keep them unique.
+    used:    HashSet<Guid>
+}
+
+/// Scramble the bits of the value, so that similar ids do not produce similar
+/// pseudonyms. This is the finalizer of SplitMix64, which, unlike the hashers
+/// of the standard library, is guaranteed to stay the same.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+impl Anonymizer {
+    pub fn new(seed: u64) -> Anonymizer {
+        Anonymizer {
+            seed,
+            players: HashMap::new(),
+            names: HashMap::new(),
+            used: HashSet::new()
+        }
+    }
+
+    /// A new id for the player, which is still recognised as a player.
+    fn pseudonym_guid(&self, guid: &Guid, attempt: u64) -> Guid {
+        let base = match guid {
+            Guid::Legacy(raw) => *raw,
+            Guid::Text(text) => text.bytes().fold(0, |h, b| mix(h ^ b as u64))
+        };
+        let hash = mix(mix(self.seed ^ base) ^ attempt);
+
+        match guid {
+            // Player ids have their highest 16 bits empty and are never zero.
+            Guid::Legacy(_) => Guid::Legacy((hash & 0x0000_FFFF_FFFF_FFFF).max(1)),
+            // Like `Player-1234-0ABCDEF`, where the server is kept.
+            Guid::Text(text) => {
+                let server = text.split('-').nth(1).unwrap_or("0");
+                Guid::Text(format!("Player-{}-{:08X}", server, hash as u32).into())
+            }
+        }
+    }
+
+    /// The pseudonym of the unit if it is a player, with the same flags, or
+    /// the unit itself otherwise.
+    pub fn unit(&mut self, unit: &Unit) -> Unit {
+        let guid = unit.guid();
+        if guid.typ() != GuidType::Player {
+            return unit.clone();
+        }
+
+        if !self.players.contains_key(&guid) {
+            let mut attempt = 0;
+            let mut pseudonym = self.pseudonym_guid(&guid, attempt);
+            while self.used.contains(&pseudonym) {
+                attempt += 1;
+                pseudonym = self.pseudonym_guid(&guid, attempt);
+            }
+
+            let name = format!("Player{}", self.players.len() + 1);
+            self.used.insert(pseudonym.clone());
+            self.names.insert(unit.name().clone(), name.clone());
+            self.players.insert(guid.clone(), (pseudonym, name));
+        }
+
+        let (pseudonym, name) = &self.players[&guid];
+        Unit::with_guid(pseudonym.clone(), name.clone(), unit.flags())
+    }
+
+    /// The payload with all fields of events of unknown types or of raw
+    /// payloads and suffix parameters replaced, if they match a known player,
+    /// or `None` if the payload has no raw fields.
+    fn payload(&self, payload: &Payload) -> Option<Payload> {
+        let fields = |fields: &[String]| -> Box<[String]> {
+            fields
+                .iter()
+                .map(|field| self.raw_field(field).unwrap_or_else(|| field.clone()))
+                .collect()
+        };
+
+        match payload {
+            Payload::Combat {
+                prefix,
+                suffix: SuffixParams::Raw(raw)
+            } => Some(Payload::Combat {
+                prefix: prefix.clone(),
+                suffix: SuffixParams::Raw(fields(raw))
+            }),
+            _ => payload.raw().map(|raw| Payload::Raw(fields(raw)))
+        }
+    }
+
+    /// The pseudonym for a raw field containing the id or the quoted name of
+    /// a known player.
+    fn raw_field(&self, field: &str) -> Option<String> {
+        if let Some((pseudonym, _)) = Guid::parse(field).and_then(|guid| self.players.get(&guid)) {
+            return Some(pseudonym.to_string());
+        }
+
+        let name = field.strip_prefix('"')?.strip_suffix('"')?;
+        self.names
+            .get(name)
+            .map(|pseudonym| format!("\"{}\"", pseudonym))
+    }
+
+    /// A copy of the log with all players replaced by their pseudonyms. The
+    /// events keep referring to the same entries of the unit table, which
+    /// now hold the pseudonyms. The columns of the lines that the events do
+    /// not keep are replaced like the fields of raw payloads.
+    pub fn log(&mut self, log: &Log) -> Log {
+        let units = log
+            .units()
+            .units()
+            .iter()
+            .map(|unit| self.unit(unit))
+            .collect();
+        let mut table = UnitTable::from_units(units);
+        if let Some(start) = log.units().start() {
+            table.set_start(start);
+        }
+
+        let events = log
+            .events()
+            .iter()
+            .map(|event| {
+                let mut event = match self.payload(event.payload()) {
+                    Some(payload) => event.with_payload(payload),
+                    None => event.clone()
+                };
+                if let Some(extras) = event.extras() {
+                    let cols: Vec<String> = log
+                        .units()
+                        .extras(extras)
+                        .map(|col| self.raw_field(col).unwrap_or_else(|| col.to_string()))
+                        .collect();
+                    event.set_extras(table.add_extras(cols.iter().map(String::as_str)));
+                }
+                event
+            })
+            .collect();
+
+        Log::from_events(events, table)
+    }
+}
+
+impl Log {
+    /// A copy of the log with all players replaced by pseudonyms, which are
+    /// determined by the seed. NPCs keep their names.
+    pub fn anonymized(&self, seed: u64) -> Log { Anonymizer::new(seed).log(self) }
+}
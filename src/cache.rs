@@ -0,0 +1,822 @@
+//! A compact binary form of parsed logs, so that large logs do not have to be
+//! parsed again every time they are opened.
+//!
+//! A cache starts with the magic bytes `WLPC` and the version of the format,
+//! followed by the table of all strings, the table of all units, the start of
+//! the log and finally the events and rejected lines. Units and strings are
+//! only stored once and referred to by their index. Numbers are written as
+//! LEB128 varints, signed ones zigzag encoded, and the time of every event as
+//! the difference in milliseconds to the event before it. The line and position
+//! an event has been read from are stored as the difference to the event before
+//! it as well. Every event ends with the columns of its line it does not keep,
+//! like the raid flags of versioned logs.
+
+use crate::event::{Event, EventType, Prefix, Suffix};
+use crate::format::LogFormat;
+use crate::guid::Guid;
+use crate::log::{ParseError, ParseErrorType, ParseReport};
+use crate::payload::*;
+use crate::unit::Unit;
+use crate::unit_flags::UnitFlags;
+use crate::unit_table::{UnitRef, UnitTable};
+use chrono::DateTime;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"WLPC";
+
+/// The version of the cache format, which has to be increased whenever the
+/// format changes. Caches of other versions are rejected.
+pub const CACHE_VERSION: u32 = 6;
+
+const PREFIXES: [Prefix; 8] = [
+    Prefix::Swing,
+    Prefix::Range,
+    Prefix::Spell,
+    Prefix::SpellPeriodic,
+    Prefix::SpellBuilding,
+    Prefix::Environmental,
+    Prefix::DamageShield,
+    Prefix::DamageSplit
+];
+
+const SUFFIXES: [Suffix; 27] = [
+    Suffix::Damage,
+    Suffix::Missed,
+    Suffix::Heal,
+    Suffix::Energize,
+    Suffix::Drain,
+    Suffix::Leech,
+    Suffix::Interrupt,
+    Suffix::Dispel,
+    Suffix::DispelFailed,
+    Suffix::Stolen,
+    Suffix::ExtraAttacks,
+    Suffix::AuraApplied,
+    Suffix::AuraRemoved,
+    Suffix::AuraAppliedDose,
+    Suffix::AuraRemovedDose,
+    Suffix::AuraRefresh,
+    Suffix::AuraBroken,
+    Suffix::AuraBrokenSpell,
+    Suffix::CastStart,
+    Suffix::CastSuccess,
+    Suffix::CastFailed,
+    Suffix::Instakill,
+    Suffix::DurabilityDamage,
+    Suffix::DurabilityDamageAll,
+    Suffix::Create,
+    Suffix::Summon,
+    Suffix::Resurrect
+];
+
+const MISS_TYPES: [MissType; 10] = [
+    MissType::Absorb,
+    MissType::Block,
+    MissType::Deflect,
+    MissType::Dodge,
+    MissType::Evade,
+    MissType::Immune,
+    MissType::Miss,
+    MissType::Parry,
+    MissType::Reflect,
+    MissType::Resist
+];
+
+const ENVIRONMENTAL_TYPES: [EnvironmentalType; 6] = [
+    EnvironmentalType::Drowning,
+    EnvironmentalType::Falling,
+    EnvironmentalType::Fatigue,
+    EnvironmentalType::Fire,
+    EnvironmentalType::Lava,
+    EnvironmentalType::Slime
+];
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid cache: {}", what)
+    )
+}
+
+/// Encodes the events, collecting the strings and units into their tables.
+#[derive(Default)]
+struct Writer {
+    body:      Vec<u8>,
+    strings:   HashMap<String, u64>,
+    /// The units in the order of their index.
+    unit_list: Vec<Unit>,
+    last:      i64,
+    /// The line and position of the event before.
+    position:  (i64, i64)
+}
+
+impl Writer {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.body.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.body.push(value as u8);
+    }
+
+    fn signed(&mut self, value: i64) { self.varint(((value << 1) ^ (value >> 63)) as u64) }
+
+    fn byte(&mut self, value: u8) { self.body.push(value); }
+
+    fn bool(&mut self, value: bool) { self.byte(value as u8); }
+
+    fn opt(&mut self, value: Option<u64>) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            self.varint(value);
+        }
+    }
+
+    /// Add the string to the table, returning its index.
+    fn intern(&mut self, s: &str) -> u64 {
+        let next = self.strings.len() as u64;
+        *self.strings.entry(s.to_string()).or_insert(next)
+    }
+
+    fn str(&mut self, s: &str) {
+        let index = self.intern(s);
+        self.varint(index);
+    }
+
+    fn unit(&mut self, unit: Option<UnitRef>) {
+        self.varint(unit.map_or(0, |unit| unit.index() as u64 + 1));
+    }
+
+    /// Add the strings of the units to the table, which are written in front
+    /// of the events.
+    fn units(&mut self, units: &UnitTable) {
+        for unit in units.units() {
+            if let Guid::Text(text) = unit.guid() {
+                self.intern(&text);
+            }
+            self.intern(unit.name());
+        }
+        self.unit_list = units.units().clone();
+    }
+
+    fn spell(&mut self, spell: &Spell) {
+        self.varint(spell.id() as u64);
+        self.str(spell.name());
+        self.varint(spell.school() as u64);
+    }
+
+    fn format(&mut self, format: LogFormat) {
+        match format {
+            LogFormat::Legacy => self.byte(0),
+            LogFormat::Versioned { version, advanced } => {
+                self.byte(1);
+                self.varint(version as u64);
+                self.bool(advanced);
+            }
+        }
+    }
+
+    fn typ(&mut self, typ: &EventType) {
+        match typ {
+            EventType::Combat(prefix, suffix) => {
+                self.byte(0);
+                self.byte(*prefix as u8);
+                self.byte(*suffix as u8);
+            }
+            EventType::EnchantApplied => self.byte(1),
+            EventType::EnchantRemoved => self.byte(2),
+            EventType::PartyKill => self.byte(3),
+            EventType::UnitDied => self.byte(4),
+            EventType::UnitDestroyed => self.byte(5),
+            EventType::CombatLogVersion => self.byte(6),
+            EventType::EncounterStart => self.byte(7),
+            EventType::EncounterEnd => self.byte(8),
+            EventType::Unknown(name) => {
+                self.byte(9);
+                self.str(name);
+            }
+        }
+    }
+
+    fn prefix(&mut self, params: &PrefixParams) {
+        match params {
+            PrefixParams::None => self.byte(0),
+            PrefixParams::Spell(spell) => {
+                self.byte(1);
+                self.spell(spell);
+            }
+            PrefixParams::Environmental(typ) => {
+                self.byte(2);
+                self.byte(*typ as u8);
+            }
+        }
+    }
+
+    fn suffix(&mut self, params: &SuffixParams) {
+        match params {
+            SuffixParams::None => self.byte(0),
+            SuffixParams::Damage(damage) => {
+                self.byte(1);
+                self.varint(damage.amount());
+                self.varint(damage.overkill());
+                self.varint(damage.school() as u64);
+                self.varint(damage.resisted());
+                self.varint(damage.blocked());
+                self.varint(damage.absorbed());
+                self.bool(damage.critical());
+                self.bool(damage.glancing());
+                self.bool(damage.crushing());
+            }
+            SuffixParams::Missed(miss) => {
+                self.byte(2);
+                self.byte(miss.typ() as u8);
+                self.opt(miss.amount());
+            }
+            SuffixParams::Heal(heal) => {
+                self.byte(3);
+                self.varint(heal.amount());
+                self.varint(heal.overheal());
+                self.varint(heal.absorbed());
+                self.bool(heal.critical());
+            }
+            SuffixParams::Energize { amount, power_type } => {
+                self.byte(4);
+                self.varint(u64::from(*amount));
+                self.signed(*power_type as i64);
+            }
+            SuffixParams::Drain {
+                amount,
+                power_type,
+                extra_amount
+            } => {
+                self.byte(5);
+                self.varint(u64::from(*amount));
+                self.signed(*power_type as i64);
+                self.varint(u64::from(*extra_amount));
+            }
+            SuffixParams::ExtraSpell { extra_spell } => {
+                self.byte(6);
+                self.spell(extra_spell);
+            }
+            SuffixParams::Dispel { extra_spell, typ } => {
+                self.byte(7);
+                self.spell(extra_spell);
+                self.bool(*typ == AuraType::Debuff);
+            }
+            SuffixParams::ExtraAttacks { amount } => {
+                self.byte(8);
+                self.varint(u64::from(*amount));
+            }
+            SuffixParams::Aura { typ, stacks } => {
+                self.byte(9);
+                self.bool(*typ == AuraType::Debuff);
+                self.opt(stacks.map(u64::from));
+            }
+            SuffixParams::CastFailed { reason } => {
+                self.byte(10);
+                self.str(reason);
+            }
+            SuffixParams::Raw(fields) => {
+                self.byte(11);
+                self.raw(fields);
+            }
+        }
+    }
+
+    fn payload(&mut self, payload: &Payload) {
+        match payload {
+            Payload::Empty => self.byte(0),
+            Payload::Combat { prefix, suffix } => {
+                self.byte(1);
+                self.prefix(prefix);
+                self.suffix(suffix);
+            }
+            Payload::Enchant(enchant) => {
+                self.byte(2);
+                self.str(enchant.name());
+                self.varint(enchant.item_id() as u64);
+                self.str(enchant.item_name());
+            }
+            Payload::Version(version) => {
+                self.byte(3);
+                self.format(version.format());
+                self.bool(version.build().is_some());
+                if let Some(build) = version.build() {
+                    self.str(build);
+                }
+                self.opt(version.project().map(u64::from));
+            }
+            Payload::EncounterStart(start) => {
+                self.byte(4);
+                self.varint(start.id() as u64);
+                self.str(start.name());
+                self.varint(start.difficulty() as u64);
+                self.varint(start.group_size() as u64);
+                self.opt(start.instance().map(u64::from));
+            }
+            Payload::EncounterEnd(end) => {
+                self.byte(5);
+                self.varint(end.id() as u64);
+                self.str(end.name());
+                self.varint(end.difficulty() as u64);
+                self.varint(end.group_size() as u64);
+                self.bool(end.success());
+                self.opt(end.fight_time().map(u64::from));
+            }
+            // The name of an unknown event type is written with the type, so
+            // its fields are written like raw ones.
+            Payload::Unknown(unknown) => {
+                self.byte(6);
+                self.raw(unknown.fields());
+            }
+            Payload::Raw(fields) => {
+                self.byte(6);
+                self.raw(fields);
+            }
+        }
+    }
+
+    fn raw(&mut self, fields: &[String]) {
+        self.varint(fields.len() as u64);
+        for field in fields {
+            self.str(field);
+        }
+    }
+
+    fn event(&mut self, event: &Event, units: &UnitTable) {
+        let time = event.time(units).and_utc().timestamp_millis();
+        self.signed(time - self.last);
+        self.last = time;
+
+        let position = (
+            event.line().unwrap_or(0) as i64,
+            event.offset().unwrap_or(0) as i64
+        );
+        self.signed(position.0 - self.position.0);
+        self.signed(position.1 - self.position.1);
+        self.position = position;
+
+        self.typ(&event.typ());
+        self.unit(event.source_ref());
+        self.unit(event.target_ref());
+        self.payload(event.payload());
+
+        let extras: Vec<&str> = event
+            .extras()
+            .map(|extras| units.extras(extras).collect())
+            .unwrap_or_default();
+        self.varint(extras.len() as u64);
+        for col in extras {
+            self.str(col);
+        }
+    }
+
+    fn report(&mut self, report: &ParseReport) {
+        self.varint(report.rejected().len() as u64);
+        for rejected in report.rejected() {
+            self.varint(rejected.line() as u64);
+            self.str(rejected.raw());
+            self.varint(rejected.col() as u64);
+            match rejected.typ() {
+                ParseErrorType::WrongHeadLength => self.byte(0),
+                ParseErrorType::WrongTimeFormat => self.byte(1),
+                ParseErrorType::InvalidArg => self.byte(2),
+                ParseErrorType::UnknownEventType(name) => {
+                    self.byte(3);
+                    self.str(&name);
+                }
+                // Reading errors can only be kept as their message.
+                ParseErrorType::IOErr(err) => {
+                    self.byte(4);
+                    self.str(&err.to_string());
+                }
+            }
+        }
+    }
+
+    /// Put the header and the tables in front of the encoded events.
+    fn finish(self) -> Vec<u8> {
+        let mut head = Writer::default();
+        head.body.extend_from_slice(MAGIC);
+        head.body.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+
+        let mut strings: Vec<(&String, &u64)> = self.strings.iter().collect();
+        strings.sort_by_key(|(_, index)| **index);
+        head.varint(strings.len() as u64);
+        for (s, _) in strings {
+            head.varint(s.len() as u64);
+            head.body.extend_from_slice(s.as_bytes());
+        }
+
+        head.varint(self.unit_list.len() as u64);
+        for unit in &self.unit_list {
+            match unit.guid() {
+                Guid::Legacy(raw) => {
+                    head.byte(0);
+                    head.varint(raw);
+                }
+                Guid::Text(text) => {
+                    head.byte(1);
+                    head.varint(self.strings[&*text]);
+                }
+            }
+            head.varint(self.strings[unit.name()]);
+            head.varint(unit.flags().bits() as u64);
+        }
+
+        head.body.extend(self.body);
+        head.body
+    }
+}
+
+/// Decodes a cache, which has to be entirely in memory.
+struct Reader<'a> {
+    buf:      &'a [u8],
+    pos:      usize,
+    strings:  Vec<Arc<str>>,
+    /// The spells read so far by their id, the index of their name and their
+    /// school.
+    spells:   HashMap<(u32, usize, u32), Spell>,
+    units:    UnitTable,
+    last:     i64,
+    /// The line and position of the event before.
+    position: (i64, i64)
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let byte = *self
+            .buf
+            .get(self.pos)
+            .ok_or_else(|| invalid("unexpected end"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(invalid("number too long"))
+    }
+
+    fn num<T: TryFrom<u64>>(&mut self) -> io::Result<T> {
+        T::try_from(self.varint()?).map_err(|_| invalid("number out of range"))
+    }
+
+    fn signed(&mut self) -> io::Result<i64> {
+        let value = self.varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    /// Read a difference and add it to the value it is relative to.
+    fn delta(&mut self, value: i64, what: &str) -> io::Result<i64> {
+        value
+            .checked_add(self.signed()?)
+            .ok_or_else(|| invalid(what))
+    }
+
+    fn bool(&mut self) -> io::Result<bool> { Ok(self.byte()? != 0) }
+
+    fn opt<T: TryFrom<u64>>(&mut self) -> io::Result<Option<T>> {
+        if self.bool()? {
+            self.num().map(Some)
+        }
+        else {
+            Ok(None)
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or_else(|| invalid("unexpected end"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn str(&mut self) -> io::Result<Arc<str>> {
+        let index: usize = self.num()?;
+        self.strings
+            .get(index)
+            .cloned()
+            .ok_or_else(|| invalid("unknown string"))
+    }
+
+    fn unit(&mut self) -> io::Result<Option<UnitRef>> {
+        match self.num::<usize>()? {
+            0 => Ok(None),
+            index if index <= self.units.len() => Ok(Some(UnitRef::new(index - 1))),
+            _ => Err(invalid("unknown unit"))
+        }
+    }
+
+    fn table<T: Copy>(&mut self, table: &[T]) -> io::Result<T> {
+        let index = self.byte()? as usize;
+        table
+            .get(index)
+            .copied()
+            .ok_or_else(|| invalid("unknown variant"))
+    }
+
+    fn aura_type(&mut self) -> io::Result<AuraType> {
+        Ok(if self.bool()? {
+            AuraType::Debuff
+        }
+        else {
+            AuraType::Buff
+        })
+    }
+
+    /// Read a spell, which is shared with the equal spells read before.
+    fn spell(&mut self) -> io::Result<Spell> {
+        let (id, name, school) = (self.num()?, self.num()?, self.num()?);
+        if let Some(spell) = self.spells.get(&(id, name, school)) {
+            return Ok(spell.clone());
+        }
+        let names = self.strings.get(name).cloned();
+        let spell = Spell::new(id, names.ok_or_else(|| invalid("unknown string"))?, school);
+        self.spells.insert((id, name, school), spell.clone());
+        Ok(spell)
+    }
+
+    fn format(&mut self) -> io::Result<LogFormat> {
+        Ok(match self.byte()? {
+            0 => LogFormat::Legacy,
+            1 => LogFormat::Versioned {
+                version:  self.num()?,
+                advanced: self.bool()?
+            },
+            _ => return Err(invalid("unknown format"))
+        })
+    }
+
+    fn typ(&mut self) -> io::Result<EventType> {
+        Ok(match self.byte()? {
+            0 => EventType::Combat(self.table(&PREFIXES)?, self.table(&SUFFIXES)?),
+            1 => EventType::EnchantApplied,
+            2 => EventType::EnchantRemoved,
+            3 => EventType::PartyKill,
+            4 => EventType::UnitDied,
+            5 => EventType::UnitDestroyed,
+            6 => EventType::CombatLogVersion,
+            7 => EventType::EncounterStart,
+            8 => EventType::EncounterEnd,
+            9 => EventType::Unknown(self.str()?),
+            _ => return Err(invalid("unknown event type"))
+        })
+    }
+
+    fn prefix(&mut self) -> io::Result<PrefixParams> {
+        Ok(match self.byte()? {
+            0 => PrefixParams::None,
+            1 => PrefixParams::Spell(self.spell()?),
+            2 => PrefixParams::Environmental(self.table(&ENVIRONMENTAL_TYPES)?),
+            _ => return Err(invalid("unknown prefix parameters"))
+        })
+    }
+
+    fn suffix(&mut self) -> io::Result<SuffixParams> {
+        Ok(match self.byte()? {
+            0 => SuffixParams::None,
+            1 => SuffixParams::Damage(Damage {
+                amount:   self.num()?,
+                overkill: self.num()?,
+                school:   self.num()?,
+                resisted: self.num()?,
+                blocked:  self.num()?,
+                absorbed: self.num()?,
+                critical: self.bool()?,
+                glancing: self.bool()?,
+                crushing: self.bool()?
+            }),
+            2 => SuffixParams::Missed(Miss::new(self.table(&MISS_TYPES)?, self.opt()?)),
+            3 => SuffixParams::Heal(Heal {
+                amount:   self.num()?,
+                overheal: self.num()?,
+                absorbed: self.num()?,
+                critical: self.bool()?
+            }),
+            4 => SuffixParams::Energize {
+                amount:     self.num()?,
+                power_type: self.signed()? as i32
+            },
+            5 => SuffixParams::Drain {
+                amount: self.num()?,
+                power_type: self.signed()? as i32,
+                extra_amount: self.num()?
+            },
+            6 => SuffixParams::ExtraSpell {
+                extra_spell: self.spell()?
+            },
+            7 => SuffixParams::Dispel {
+                extra_spell: self.spell()?,
+                typ: self.aura_type()?
+            },
+            8 => SuffixParams::ExtraAttacks {
+                amount: self.num()?
+            },
+            9 => SuffixParams::Aura {
+                typ:    self.aura_type()?,
+                stacks: self.opt()?
+            },
+            10 => SuffixParams::CastFailed {
+                reason: self.str()?
+            },
+            11 => SuffixParams::Raw(self.raw()?),
+            _ => return Err(invalid("unknown suffix parameters"))
+        })
+    }
+
+    fn payload(&mut self) -> io::Result<Payload> {
+        Ok(match self.byte()? {
+            0 => Payload::Empty,
+            1 => Payload::Combat {
+                prefix: self.prefix()?,
+                suffix: self.suffix()?
+            },
+            2 => Payload::Enchant(Box::new(Enchant {
+                name:      self.str()?,
+                item_id:   self.num()?,
+                item_name: self.str()?
+            })),
+            3 => Payload::Version(Box::new(Version {
+                format:  self.format()?,
+                build:   if self.bool()? {
+                    Some(self.str()?)
+                }
+                else {
+                    None
+                },
+                project: self.opt()?
+            })),
+            4 => Payload::EncounterStart(Box::new(EncounterStart {
+                id: self.num()?,
+                name: self.str()?,
+                difficulty: self.num()?,
+                group_size: self.num()?,
+                instance: self.opt()?
+            })),
+            5 => Payload::EncounterEnd(Box::new(EncounterEnd {
+                id: self.num()?,
+                name: self.str()?,
+                difficulty: self.num()?,
+                group_size: self.num()?,
+                success: self.bool()?,
+                fight_time: self.opt()?
+            })),
+            6 => Payload::Raw(self.raw()?),
+            _ => return Err(invalid("unknown payload"))
+        })
+    }
+
+    fn raw(&mut self) -> io::Result<Box<[String]>> {
+        let len: usize = self.num()?;
+        (0..len)
+            .map(|_| self.str().map(|field| field.to_string()))
+            .collect()
+    }
+
+    fn event(&mut self) -> io::Result<Event> {
+        self.last = self.delta(self.last, "time out of range")?;
+        let time = DateTime::from_timestamp_millis(self.last)
+            .ok_or_else(|| invalid("time out of range"))?
+            .naive_utc();
+
+        self.position.0 = self.delta(self.position.0, "line out of range")?;
+        self.position.1 = self.delta(self.position.1, "position out of range")?;
+        let line = usize::try_from(self.position.0).map_err(|_| invalid("line out of range"))?;
+        let offset =
+            u64::try_from(self.position.1).map_err(|_| invalid("position out of range"))?;
+
+        let typ = self.typ()?;
+        let (source, target) = (self.unit()?, self.unit()?);
+        let payload = self.payload()?;
+        let mut event = Event::new(time, typ, source, target, payload, &mut self.units)
+            .map_err(|_| invalid("time out of range"))?;
+        event.set_position(line, offset);
+
+        let len: usize = self.num()?;
+        let extras = (0..len)
+            .map(|_| self.str())
+            .collect::<io::Result<Vec<_>>>()?;
+        event.set_extras(self.units.add_extras(extras.iter().map(|col| &**col)));
+        Ok(event)
+    }
+
+    fn error(&mut self) -> io::Result<ParseError> {
+        let col = self.num()?;
+        let typ = match self.byte()? {
+            0 => ParseErrorType::WrongHeadLength,
+            1 => ParseErrorType::WrongTimeFormat,
+            2 => ParseErrorType::InvalidArg,
+            3 => ParseErrorType::UnknownEventType(self.str()?.to_string()),
+            4 => ParseErrorType::IOErr(Arc::new(io::Error::other(self.str()?.to_string()))),
+            _ => return Err(invalid("unknown error"))
+        };
+
+        Ok(ParseError::new(typ, col))
+    }
+}
+
+/// Encode the events and the report of a log.
+pub(crate) fn encode(events: &[Event], units: &UnitTable, report: &ParseReport) -> Vec<u8> {
+    let mut writer = Writer::default();
+    writer.units(units);
+    let start = units
+        .start()
+        .map(|start| start.and_utc().timestamp_millis());
+    writer.bool(start.is_some());
+    if let Some(start) = start {
+        writer.signed(start);
+    }
+    writer.varint(events.len() as u64);
+    for event in events {
+        writer.event(event, units);
+    }
+    writer.report(report);
+    writer.finish()
+}
+
+/// Decode the events and the report of a log, failing if the cache has been
+/// written in another version of the format.
+pub(crate) fn decode(buf: &[u8]) -> io::Result<(Vec<Event>, UnitTable, ParseReport)> {
+    if !buf.starts_with(MAGIC) {
+        return Err(invalid("not a cache"));
+    }
+
+    let mut reader = Reader {
+        buf,
+        pos: MAGIC.len(),
+        strings: Vec::new(),
+        spells: HashMap::new(),
+        units: UnitTable::new(),
+        last: 0,
+        position: (0, 0)
+    };
+
+    let version = u32::from_le_bytes([
+        reader.byte()?,
+        reader.byte()?,
+        reader.byte()?,
+        reader.byte()?
+    ]);
+    if version != CACHE_VERSION {
+        return Err(invalid(&format!("unsupported version {}", version)));
+    }
+
+    let len: usize = reader.num()?;
+    for _ in 0..len {
+        let size = reader.num()?;
+        let s =
+            std::str::from_utf8(reader.bytes(size)?).map_err(|_| invalid("malformed string"))?;
+        reader.strings.push(s.into());
+    }
+
+    let len: usize = reader.num()?;
+    let mut units = Vec::with_capacity(len.min(buf.len()));
+    for _ in 0..len {
+        let guid = match reader.byte()? {
+            0 => Guid::Legacy(reader.varint()?),
+            1 => Guid::Text(reader.str()?),
+            _ => return Err(invalid("unknown guid"))
+        };
+        let name = reader.str()?.to_string();
+        let flags = UnitFlags::new(reader.num()?);
+        units.push(Unit::with_guid(guid, name, flags));
+    }
+    reader.units = UnitTable::from_units(units);
+    if reader.bool()? {
+        let start = reader.signed()?;
+        let start =
+            DateTime::from_timestamp_millis(start).ok_or_else(|| invalid("start out of range"))?;
+        reader.units.set_start(start.naive_utc());
+    }
+
+    let len: usize = reader.num()?;
+    let mut events = Vec::with_capacity(len.min(buf.len()));
+    for _ in 0..len {
+        events.push(reader.event()?);
+    }
+
+    let mut report = ParseReport::new();
+    let len: usize = reader.num()?;
+    for _ in 0..len {
+        let line = reader.num()?;
+        let raw = reader.str()?;
+        let error = reader.error()?;
+        report.reject(line, &raw, error);
+    }
+
+    Ok((events, reader.units, report))
+}
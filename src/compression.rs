@@ -0,0 +1,68 @@
+//! Reading logs that have been archived compressed. The compression is
+//! detected by the magic bytes at the start of the file, so the name of the
+//! file does not matter. Each compression is only supported with the feature
+//! of the same name, which are both enabled by default.
+
+#[cfg(feature = "gzip")]
+use flate2::bufread::MultiGzDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// The ways a log file may be compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+impl Compression {
+    /// Detect the compression from the first bytes of a file.
+    pub fn detect(start: &[u8]) -> Compression {
+        if start.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        }
+        else if start.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        }
+        else {
+            Compression::None
+        }
+    }
+}
+
+/// Wrap the reader, so that it decompresses its content while it is being
+/// read, if it is compressed. Fails with `io::ErrorKind::Unsupported` if the
+/// feature for the compression is not enabled.
+pub fn decompress<R: BufRead + Send + 'static>(
+    mut reader: R
+) -> io::Result<Box<dyn BufRead + Send>> {
+    let compression = Compression::detect(reader.fill_buf()?);
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+        #[allow(unreachable_patterns)]
+        _ => {
+            let feature = format!("{:?}", compression).to_lowercase();
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "reading logs compressed with {0} needs the `{0}` feature",
+                    feature
+                )
+            ));
+        }
+    })
+}
+
+/// Open the file at the given path for reading its decompressed content.
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn BufRead + Send>> {
+    decompress(BufReader::new(File::open(path)?))
+}
@@ -1,5 +1,8 @@
 use crate::event::*;
 use crate::unit::Unit;
+use crate::unit_table::UnitTable;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use std::cmp::max;
 use std::collections::{HashMap, HashSet};
@@ -8,31 +11,38 @@ use std::collections::{HashMap, HashSet};
 /// An Encounter starts, when no other Encounter is active and an Event with an
 /// enemy is detected. It ends when all enemies pulled in the encounter or all
 /// players present are dead.
+///
+/// The events refer to a table of only the units of the encounter, so that it
+/// can be kept without the log it has been found in.
+///
+/// With the `serde` feature, an encounter is serialized as an object with its
+/// `events`, the `involved` units and the table of `units` the events refer
+/// to.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Encounter {
     events:   Vec<Event>,
-    involved: HashSet<Unit>
+    involved: HashSet<Unit>,
+    units:    UnitTable
 }
 
 impl Encounter {
-    /// Split a given vector into all encounters contained within.
-    pub fn all_encounters(events: Vec<Event>) -> Vec<Encounter> {
+    /// Split a given vector into all encounters contained within. The units of
+    /// the events are looked up in the given table.
+    pub fn all_encounters(events: Vec<Event>, units: &UnitTable) -> Vec<Encounter> {
         // Records the lives of all hostile units as a tuple of the Unit itself,
         // the index of the first event it attacked by or has attacked a player
         // and the index of the last event recorded with it.
-        let mut life_windows: HashMap<Unit, (usize, usize)> = HashMap::new();
+        let mut life_windows: HashMap<&Unit, (usize, usize)> = HashMap::new();
 
         // First, run through the Events in positive direction and find all Units
         // starting lifetimes.
         for (i, e) in events.iter().enumerate() {
-            if e.is_hostile() {
-                if let (Some(src), Some(tgt)) = (e.source(), e.target()) {
-                    if src.is_player() && !tgt.is_player() && !life_windows.contains_key(&tgt) {
+            if e.is_hostile(units) {
+                if let (Some(src), Some(tgt)) = (e.source(units), e.target(units)) {
+                    if src.friendly() && !tgt.friendly() && !life_windows.contains_key(tgt) {
                         life_windows.insert(tgt, (i, 0));
                     }
-                    else if tgt.is_player()
-                        && !src.is_player()
-                        && !life_windows.contains_key(&src)
-                    {
+                    else if tgt.friendly() && !src.friendly() && !life_windows.contains_key(src) {
                         life_windows.insert(src, (i, 0));
                     }
                 }
@@ -43,13 +53,13 @@ impl Encounter {
         // has taken part in. This is considered the end of their life, even when
         // there has not been a UnitDied Event.
         for (i, e) in events.iter().enumerate().rev() {
-            if let Some(src) = e.source() {
-                if let Some(life) = life_windows.get_mut(&src) {
+            if let Some(src) = e.source(units) {
+                if let Some(life) = life_windows.get_mut(src) {
                     life.1 = max(i, life.1);
                 }
             }
-            if let Some(tgt) = e.target() {
-                if let Some(life) = life_windows.get_mut(&tgt) {
+            if let Some(tgt) = e.target(units) {
+                if let Some(life) = life_windows.get_mut(tgt) {
                     life.1 = max(i, life.1);
                 }
             }
@@ -80,25 +90,36 @@ impl Encounter {
 
         encounter_indexes
             .iter()
-            .map(|(start, end)| Encounter::from_events(events[*start..*end + 1].to_vec()))
+            .map(|(start, end)| Encounter::from_events(events[*start..*end + 1].to_vec(), units))
             .collect()
     }
 
-    pub fn from_events(events: Vec<Event>) -> Encounter {
+    /// The encounter of the given events, whose units are looked up in the
+    /// given table and moved to the table of the encounter.
+    pub fn from_events(mut events: Vec<Event>, units: &UnitTable) -> Encounter {
         let mut involved = HashSet::new();
         for e in &events {
-            if let Some(src) = e.source() {
-                involved.insert(src);
-            };
-            if let Some(tgt) = e.target() {
-                involved.insert(tgt);
-            };
+            for unit in e.source(units).into_iter().chain(e.target(units)) {
+                if !involved.contains(unit) {
+                    involved.insert(unit.clone());
+                }
+            }
         }
 
-        Encounter { events, involved }
+        let units = units.sub_table(&mut events);
+        Encounter {
+            events,
+            involved,
+            units
+        }
     }
 
     pub fn involved(&self) -> &HashSet<Unit> { &self.involved }
 
+    /// The events of the encounter, whose units are looked up in `units`
+    /// rather than in the table of the log.
     pub fn events(&self) -> &Vec<Event> { &self.events }
+
+    /// The units the events of the encounter refer to.
+    pub fn units(&self) -> &UnitTable { &self.units }
 }
@@ -1,101 +1,348 @@
+use crate::extras::{extra_cols, ExtrasRef};
+use crate::format::LogFormat;
+use crate::guid::Guid;
 use crate::log::{ParseError, ParseErrorType};
+use crate::payload::{Names, Payload, Unknown};
+use crate::tokenizer::{tokenize, Field};
 use crate::unit::Unit;
-use chrono::NaiveDateTime;
+use crate::unit_flags::UnitFlags;
+#[cfg(feature = "serde")]
+use crate::unit_table::{millis_of, split_millis};
+use crate::unit_table::{UnitRef, UnitTable};
+use chrono::{Datelike, Local, NaiveDateTime};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum EventType {
+/// The first part of the name of a combat event, which determines the
+/// parameters directly following the target unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "SCREAMING_SNAKE_CASE")
+)]
+pub enum Prefix {
+    /// A melee swing. Has no parameters.
+    Swing,
+    /// A ranged attack. Has the spell parameters of the shot.
+    Range,
+    /// Has the spell parameters.
+    Spell,
+    /// A periodic effect of a spell. Has the spell parameters.
+    SpellPeriodic,
+    /// A spell affecting a building, like siege damage. Has the spell
+    /// parameters.
+    SpellBuilding,
+    /// Has the type of environment as its single parameter.
+    Environmental,
+    /// Damage reflected by a shield, like thorns. Has the spell parameters.
     DamageShield,
-    DamageShieldMissed,
+    /// Damage split between multiple units. Has the spell parameters.
+    DamageSplit
+}
+
+/// The last part of the name of a combat event, which determines the
+/// parameters following the prefix parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "SCREAMING_SNAKE_CASE")
+)]
+pub enum Suffix {
+    Damage,
+    Missed,
+    Heal,
+    Energize,
+    Drain,
+    Leech,
+    Interrupt,
+    Dispel,
+    DispelFailed,
+    Stolen,
+    ExtraAttacks,
+    AuraApplied,
+    AuraRemoved,
+    AuraAppliedDose,
+    AuraRemovedDose,
+    AuraRefresh,
+    AuraBroken,
+    AuraBrokenSpell,
+    CastStart,
+    CastSuccess,
+    CastFailed,
+    Instakill,
+    DurabilityDamage,
+    DurabilityDamageAll,
+    Create,
+    Summon,
+    Resurrect
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum EventType {
+    /// Most events are made up of a prefix, which describes what caused the
+    /// event, and a suffix, which describes what happened, like
+    /// `SPELL_PERIODIC` and `_DAMAGE`.
+    Combat(Prefix, Suffix),
     EnchantApplied,
-    EnvironmentalDamage,
+    EnchantRemoved,
     PartyKill,
-    RangeDamage,
-    SpellAuraApplied,
-    SpellAuraAppliedDose,
-    SpellAuraRefresh,
-    SpellAuraRemoved,
-    SpellCastFailed,
-    SpellCastStart,
-    SpellCastSuccess,
-    SpellCreate,
-    SpellDamage,
-    SpellDispel,
-    SpellEnergise,
-    SpellHeal,
-    SpellInterrupt,
-    SpellMissed,
-    SpellPeriodicDamage,
-    SpellPeriodicDrain,
-    SpellPeriodicEnergise,
-    SpellPeriodicHeal,
-    SpellPeriodicMissed,
-    SpellStolen,
-    SpellSummon,
-    SpellResurrect,
-    SwingDamage,
-    SwingMissed,
-    UnitDied
+    UnitDied,
+    UnitDestroyed,
+    /// The header of logs written by clients after Wrath of the Lich King,
+    /// which tells the format of the following lines.
+    CombatLogVersion,
+    EncounterStart,
+    EncounterEnd,
+    /// An event type this library does not know about, for instance one
+    /// added by a newer client or a private server. Its fields are kept in
+    /// `Payload::Unknown`.
+    Unknown(Arc<str>)
 }
 
+/// The type of an event as it is stored in the event. The name of an unknown
+/// type is kept in its `Payload::Unknown`, so that it takes no space in the
+/// other events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Combat(Prefix, Suffix),
+    EnchantApplied,
+    EnchantRemoved,
+    PartyKill,
+    UnitDied,
+    UnitDestroyed,
+    CombatLogVersion,
+    EncounterStart,
+    EncounterEnd,
+    Unknown
+}
+
+/// A single line of the log. Its units are stored in the `UnitTable` of the
+/// log, like `Log::units`, and the spells and names in its payload are shared
+/// with the other events of the log, so that only rare events like the start
+/// of an encounter allocate memory of their own.
+///
+/// With the `serde` feature, an event is serialized as an object with the
+/// fields `time` with the milliseconds since the start of the log, `type` with
+/// the name of the event type as written in the log, `source` and `target` as
+/// the index of their unit or `null` and `payload`, as well as the `line` and
+/// `offset` it has been read from and the index of its `extras` in the unit
+/// table, if it has any.
 #[derive(Clone, Debug)]
 pub struct Event {
-    /// The time this event occured, depending on the client time used for
-    /// logging. Therefore it can only be trusted to be correct *relative*
-    /// to other events of the same log file.
-    time:   NaiveDateTime,
+    /// The time this event occured in seconds since the start of the log,
+    /// depending on the client time used for logging. Therefore it can only be
+    /// trusted to be correct *relative* to other events of the same log file.
+    secs:    i32,
+    /// The milliseconds to add to `secs`.
+    millis:  u16,
     /// The type of this event
-    typ:    EventType,
+    kind:    Kind,
     /// The unit that is the cause or source of this event or `None` if no such
-    /// unit exists, for instance with `EventType::EnvironmentalDamage`.
-    source: Option<Unit>,
+    /// unit exists, for instance with environmental damage.
+    source:  Option<UnitRef>,
     /// The unit that is the target or receiver of this event or `None` if no
-    /// such unit exists, for instance with `EventType::SpellCastSuccess`
-    target: Option<Unit>,
-    /// Many events have an amount of for instance damage or healing. In that
-    /// case, this will be set. For events like `EventType::UnitDied` this
-    /// will be set to `None`.
-    amount: Option<u64>
+    /// such unit exists, for instance with most casts of spells.
+    target:  Option<UnitRef>,
+    /// Everything following the target unit, like the spell and the amount of
+    /// damage or healing. Which variant is used depends on the type of the
+    /// event.
+    payload: Payload,
+    /// The number of the line the event has been read from, starting at one,
+    /// or zero if it has not been read from a log.
+    line:    u32,
+    /// The position of the start of that line in bytes, counted in the
+    /// decompressed text of the log.
+    offset:  u64,
+    /// The columns of the line that the event does not keep, like the raid
+    /// flags of versioned logs, which are stored in the unit table.
+    extras:  Option<ExtrasRef>
+}
+
+/// The serialized form of an `Event`, with its time in milliseconds.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct EventRepr<P> {
+    time:    i64,
+    #[serde(rename = "type")]
+    typ:     EventType,
+    source:  Option<UnitRef>,
+    target:  Option<UnitRef>,
+    payload: P,
+    #[serde(default)]
+    line:    u32,
+    #[serde(default)]
+    offset:  u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extras:  Option<ExtrasRef>
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Event {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        EventRepr {
+            time:    millis_of((self.secs, self.millis)),
+            typ:     self.typ(),
+            source:  self.source,
+            target:  self.target,
+            payload: &self.payload,
+            line:    self.line,
+            offset:  self.offset,
+            extras:  self.extras
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Event {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Event, D::Error> {
+        let repr = EventRepr::<Payload>::deserialize(deserializer)?;
+        let (secs, millis) =
+            split_millis(repr.time).ok_or_else(|| serde::de::Error::custom("time out of range"))?;
+        let (kind, payload) = Kind::of(repr.typ, repr.payload);
+        Ok(Event {
+            secs,
+            millis,
+            kind,
+            source: repr.source,
+            target: repr.target,
+            payload,
+            line: repr.line,
+            offset: repr.offset,
+            extras: repr.extras
+        })
+    }
+}
+
+/// The prefixes as they are written in the log. Prefixes that start with
+/// another prefix must come before it.
+const PREFIXES: [(&str, Prefix); 6] = [
+    ("SWING", Prefix::Swing),
+    ("RANGE", Prefix::Range),
+    ("SPELL_PERIODIC", Prefix::SpellPeriodic),
+    ("SPELL_BUILDING", Prefix::SpellBuilding),
+    ("SPELL", Prefix::Spell),
+    ("ENVIRONMENTAL", Prefix::Environmental)
+];
+
+/// The suffixes as they are written in the log.
+const SUFFIXES: [(&str, Suffix); 27] = [
+    ("DAMAGE", Suffix::Damage),
+    ("MISSED", Suffix::Missed),
+    ("HEAL", Suffix::Heal),
+    ("ENERGIZE", Suffix::Energize),
+    ("DRAIN", Suffix::Drain),
+    ("LEECH", Suffix::Leech),
+    ("INTERRUPT", Suffix::Interrupt),
+    ("DISPEL", Suffix::Dispel),
+    ("DISPEL_FAILED", Suffix::DispelFailed),
+    ("STOLEN", Suffix::Stolen),
+    ("EXTRA_ATTACKS", Suffix::ExtraAttacks),
+    ("AURA_APPLIED", Suffix::AuraApplied),
+    ("AURA_REMOVED", Suffix::AuraRemoved),
+    ("AURA_APPLIED_DOSE", Suffix::AuraAppliedDose),
+    ("AURA_REMOVED_DOSE", Suffix::AuraRemovedDose),
+    ("AURA_REFRESH", Suffix::AuraRefresh),
+    ("AURA_BROKEN", Suffix::AuraBroken),
+    ("AURA_BROKEN_SPELL", Suffix::AuraBrokenSpell),
+    ("CAST_START", Suffix::CastStart),
+    ("CAST_SUCCESS", Suffix::CastSuccess),
+    ("CAST_FAILED", Suffix::CastFailed),
+    ("INSTAKILL", Suffix::Instakill),
+    ("DURABILITY_DAMAGE", Suffix::DurabilityDamage),
+    ("DURABILITY_DAMAGE_ALL", Suffix::DurabilityDamageAll),
+    ("CREATE", Suffix::Create),
+    ("SUMMON", Suffix::Summon),
+    ("RESURRECT", Suffix::Resurrect)
+];
+
+impl Prefix {
+    /// The number of parameters belonging to the prefix.
+    pub fn num_params(&self) -> usize {
+        match self {
+            Prefix::Swing => 0,
+            Prefix::Environmental => 1,
+            _ => 3
+        }
+    }
+}
+
+impl Suffix {
+    pub fn from_str<S: AsRef<str>>(s: S, col: usize) -> Result<Suffix, ParseError> {
+        SUFFIXES
+            .iter()
+            .find(|(name, _)| *name == s.as_ref())
+            .map(|(_, suffix)| *suffix)
+            .ok_or_else(|| {
+                ParseError::new(
+                    ParseErrorType::UnknownEventType(s.as_ref().to_string()),
+                    col
+                )
+            })
+    }
 }
 
 impl EventType {
     pub fn from_str<S: AsRef<str>>(s: S, col: usize) -> Result<EventType, ParseError> {
         match s.as_ref() {
-            "DAMAGE_SHIELD" => Ok(EventType::DamageShield),
-            "DAMAGE_SHIELD_MISSED" => Ok(EventType::DamageShieldMissed),
+            "DAMAGE_SHIELD" => Ok(EventType::Combat(Prefix::DamageShield, Suffix::Damage)),
+            "DAMAGE_SHIELD_MISSED" => Ok(EventType::Combat(Prefix::DamageShield, Suffix::Missed)),
+            "DAMAGE_SPLIT" => Ok(EventType::Combat(Prefix::DamageSplit, Suffix::Damage)),
             "ENCHANT_APPLIED" => Ok(EventType::EnchantApplied),
-            "ENVIRONMENTAL_DAMAGE" => Ok(EventType::EnvironmentalDamage),
+            "ENCHANT_REMOVED" => Ok(EventType::EnchantRemoved),
             "PARTY_KILL" => Ok(EventType::PartyKill),
-            "RANGE_DAMAGE" => Ok(EventType::RangeDamage),
-            "SPELL_AURA_APPLIED" => Ok(EventType::SpellAuraApplied),
-            "SPELL_AURA_APPLIED_DOSE" => Ok(EventType::SpellAuraAppliedDose),
-            "SPELL_AURA_REFRESH" => Ok(EventType::SpellAuraRefresh),
-            "SPELL_AURA_REMOVED" => Ok(EventType::SpellAuraRemoved),
-            "SPELL_CAST_FAILED" => Ok(EventType::SpellCastFailed),
-            "SPELL_CAST_START" => Ok(EventType::SpellCastStart),
-            "SPELL_CAST_SUCCESS" => Ok(EventType::SpellCastSuccess),
-            "SPELL_CREATE" => Ok(EventType::SpellCreate),
-            "SPELL_DAMAGE" => Ok(EventType::SpellDamage),
-            "SPELL_DISPEL" => Ok(EventType::SpellDispel),
-            "SPELL_ENERGIZE" => Ok(EventType::SpellEnergise),
-            "SPELL_HEAL" => Ok(EventType::SpellHeal),
-            "SPELL_INTERRUPT" => Ok(EventType::SpellInterrupt),
-            "SPELL_MISSED" => Ok(EventType::SpellMissed),
-            "SPELL_PERIODIC_DAMAGE" => Ok(EventType::SpellPeriodicDamage),
-            "SPELL_PERIODIC_DRAIN" => Ok(EventType::SpellPeriodicDrain),
-            "SPELL_PERIODIC_ENERGIZE" => Ok(EventType::SpellPeriodicEnergise),
-            "SPELL_PERIODIC_HEAL" => Ok(EventType::SpellPeriodicHeal),
-            "SPELL_PERIODIC_MISSED" => Ok(EventType::SpellPeriodicMissed),
-            "SPELL_STOLEN" => Ok(EventType::SpellStolen),
-            "SPELL_SUMMON" => Ok(EventType::SpellSummon),
-            "SPELL_RESURRECT" => Ok(EventType::SpellResurrect),
-            "SWING_DAMAGE" => Ok(EventType::SwingDamage),
-            "SWING_MISSED" => Ok(EventType::SwingMissed),
             "UNIT_DIED" => Ok(EventType::UnitDied),
-            other => Err(ParseError::new(
-                ParseErrorType::UnknownEventType(other.to_string()),
-                col
-            ))
+            "UNIT_DESTROYED" => Ok(EventType::UnitDestroyed),
+            "COMBAT_LOG_VERSION" => Ok(EventType::CombatLogVersion),
+            "ENCOUNTER_START" => Ok(EventType::EncounterStart),
+            "ENCOUNTER_END" => Ok(EventType::EncounterEnd),
+            other => {
+                let unknown =
+                    || ParseError::new(ParseErrorType::UnknownEventType(other.to_string()), col);
+
+                // Anything that is not written like an event type is garbage
+                // and not an unknown event.
+                let mut chars = other.chars();
+                if !chars.next().is_some_and(|c| c.is_ascii_uppercase())
+                    || !chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+                {
+                    return Err(unknown());
+                }
+
+                // Split the name into the prefix and the rest, which must be
+                // the suffix.
+                let combat = PREFIXES.iter().find_map(|(name, prefix)| {
+                    let suffix = other.strip_prefix(name)?.strip_prefix('_')?;
+                    Some(EventType::Combat(
+                        *prefix,
+                        Suffix::from_str(suffix, col).ok()?
+                    ))
+                });
+
+                Ok(combat.unwrap_or_else(|| EventType::Unknown(other.into())))
+            }
+        }
+    }
+
+    /// The prefix of the event, if it is a combat event.
+    pub fn prefix(&self) -> Option<Prefix> {
+        match self {
+            EventType::Combat(prefix, _) => Some(*prefix),
+            _ => None
+        }
+    }
+
+    /// The suffix of the event, if it is a combat event.
+    pub fn suffix(&self) -> Option<Suffix> {
+        match self {
+            EventType::Combat(_, suffix) => Some(*suffix),
+            _ => None
         }
     }
 
@@ -104,135 +351,391 @@ impl EventType {
     /// will.
     pub fn is_hostile(&self) -> bool {
         match self {
-            EventType::DamageShield
-            | EventType::DamageShieldMissed
-            | EventType::RangeDamage
-            | EventType::SpellDamage
-            | EventType::SpellInterrupt
-            | EventType::SpellMissed
-            | EventType::SpellPeriodicDamage
-            | EventType::SpellPeriodicMissed
-            | EventType::SpellStolen
-            | EventType::SwingDamage
-            | EventType::SwingMissed => true,
+            EventType::Combat(Prefix::Environmental, _) => false,
+            EventType::Combat(_, suffix) => matches!(
+                suffix,
+                Suffix::Damage | Suffix::Missed | Suffix::Interrupt | Suffix::Stolen
+            ),
             _ => false
         }
     }
 
     /// Returns true, if the event type is a damaging event.
-    pub fn damaging(&self) -> bool {
+    pub fn damaging(&self) -> bool { self.suffix() == Some(Suffix::Damage) }
+
+    pub fn healing(&self) -> bool { self.suffix() == Some(Suffix::Heal) }
+}
+
+/// Writes the name of the event type as it is found in the log.
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            EventType::DamageShield
-            | EventType::EnvironmentalDamage
-            | EventType::RangeDamage
-            | EventType::SpellDamage
-            | EventType::SpellPeriodicDamage
-            | EventType::SwingDamage => true,
-            _ => false
+            EventType::Combat(Prefix::DamageShield, Suffix::Damage) => write!(f, "DAMAGE_SHIELD"),
+            EventType::Combat(Prefix::DamageSplit, Suffix::Damage) => write!(f, "DAMAGE_SPLIT"),
+            EventType::Combat(prefix, suffix) => {
+                let prefix = match prefix {
+                    Prefix::DamageShield => "DAMAGE_SHIELD",
+                    Prefix::DamageSplit => "DAMAGE_SPLIT",
+                    prefix => PREFIXES
+                        .iter()
+                        .find(|(_, p)| p == prefix)
+                        .map_or("", |(name, _)| name)
+                };
+                let suffix = SUFFIXES
+                    .iter()
+                    .find(|(_, s)| s == suffix)
+                    .map_or("", |(name, _)| name);
+                write!(f, "{}_{}", prefix, suffix)
+            }
+            EventType::EnchantApplied => write!(f, "ENCHANT_APPLIED"),
+            EventType::EnchantRemoved => write!(f, "ENCHANT_REMOVED"),
+            EventType::PartyKill => write!(f, "PARTY_KILL"),
+            EventType::UnitDied => write!(f, "UNIT_DIED"),
+            EventType::UnitDestroyed => write!(f, "UNIT_DESTROYED"),
+            EventType::CombatLogVersion => write!(f, "COMBAT_LOG_VERSION"),
+            EventType::EncounterStart => write!(f, "ENCOUNTER_START"),
+            EventType::EncounterEnd => write!(f, "ENCOUNTER_END"),
+            EventType::Unknown(name) => write!(f, "{}", name)
         }
     }
+}
 
-    pub fn healing(&self) -> bool {
-        match self {
-            EventType::SpellHeal | EventType::SpellPeriodicHeal => true,
-            _ => false
-        }
+impl Kind {
+    /// The kind of an event of the given type, and its payload with the name
+    /// of an unknown type.
+    fn of(typ: EventType, payload: Payload) -> (Kind, Payload) {
+        let kind = match typ {
+            EventType::Combat(prefix, suffix) => Kind::Combat(prefix, suffix),
+            EventType::EnchantApplied => Kind::EnchantApplied,
+            EventType::EnchantRemoved => Kind::EnchantRemoved,
+            EventType::PartyKill => Kind::PartyKill,
+            EventType::UnitDied => Kind::UnitDied,
+            EventType::UnitDestroyed => Kind::UnitDestroyed,
+            EventType::CombatLogVersion => Kind::CombatLogVersion,
+            EventType::EncounterStart => Kind::EncounterStart,
+            EventType::EncounterEnd => Kind::EncounterEnd,
+            EventType::Unknown(name) => {
+                let fields = match payload {
+                    Payload::Unknown(unknown) => unknown.fields,
+                    other => other.raw().map(Into::into).unwrap_or_default()
+                };
+                return (
+                    Kind::Unknown,
+                    Payload::Unknown(Box::new(Unknown { name, fields }))
+                );
+            }
+        };
+        (kind, payload)
+    }
+}
+
+/// Event types are serialized as their name in the log, like `"SPELL_DAMAGE"`.
+#[cfg(feature = "serde")]
+impl Serialize for EventType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for EventType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<EventType, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        EventType::from_str(name, 0).map_err(serde::de::Error::custom)
     }
 }
 
 impl Event {
-    pub fn time(&self) -> NaiveDateTime { self.time }
+    /// Create an event of the given time, which is stored relative to the
+    /// start of the log in the unit table. Fails if it is more than about 68
+    /// years away from the start.
+    pub(crate) fn new(
+        time: NaiveDateTime,
+        typ: EventType,
+        source: Option<UnitRef>,
+        target: Option<UnitRef>,
+        payload: Payload,
+        units: &mut UnitTable
+    ) -> Result<Event, ParseError> {
+        let (secs, millis) = units
+            .offset(time)
+            .ok_or_else(|| ParseError::new(ParseErrorType::WrongTimeFormat, 0))?;
+        let (kind, payload) = Kind::of(typ, payload);
+        Ok(Event {
+            secs,
+            millis,
+            kind,
+            source,
+            target,
+            payload,
+            line: 0,
+            offset: 0,
+            extras: None
+        })
+    }
 
-    pub fn typ(&self) -> EventType { self.typ }
+    /// Look up the time of the event, which is stored relative to the start
+    /// of the log in its unit table.
+    pub fn time(&self, units: &UnitTable) -> NaiveDateTime { units.time((self.secs, self.millis)) }
 
-    pub fn is_hostile(&self) -> bool {
-        if !self.typ.is_hostile() {
+    /// The number of the line of the log this event has been read from,
+    /// starting at one, or `None` if it has not been read from a log.
+    pub fn line(&self) -> Option<usize> { (self.line != 0).then_some(self.line as usize) }
+
+    /// The position in bytes of the start of the line this event has been read
+    /// from. For compressed logs, it is the position in the decompressed text.
+    pub fn offset(&self) -> Option<u64> { self.line().map(|_| self.offset) }
+
+    /// The same event with another payload.
+    pub(crate) fn with_payload(&self, payload: Payload) -> Event {
+        let (kind, payload) = Kind::of(self.typ(), payload);
+        Event {
+            kind,
+            payload,
+            ..self.clone()
+        }
+    }
+
+    /// Replace the names of the payload by the equal names of other events.
+    pub(crate) fn share_names(&mut self, names: &mut Names) { self.payload.share_names(names); }
+
+    /// Record the line the event has been read from.
+    pub(crate) fn set_position(&mut self, line: usize, offset: u64) {
+        // No log has more lines, so the position is simply forgotten.
+        self.line = u32::try_from(line).unwrap_or(0);
+        self.offset = offset;
+    }
+
+    pub fn typ(&self) -> EventType {
+        match self.kind {
+            Kind::Combat(prefix, suffix) => EventType::Combat(prefix, suffix),
+            Kind::EnchantApplied => EventType::EnchantApplied,
+            Kind::EnchantRemoved => EventType::EnchantRemoved,
+            Kind::PartyKill => EventType::PartyKill,
+            Kind::UnitDied => EventType::UnitDied,
+            Kind::UnitDestroyed => EventType::UnitDestroyed,
+            Kind::CombatLogVersion => EventType::CombatLogVersion,
+            Kind::EncounterStart => EventType::EncounterStart,
+            Kind::EncounterEnd => EventType::EncounterEnd,
+            Kind::Unknown => match &self.payload {
+                Payload::Unknown(unknown) => EventType::Unknown(unknown.name.clone()),
+                _ => unreachable!("Events of unknown types keep their name in their payload")
+            }
+        }
+    }
+
+    /// Returns true, if the type of the event is hostile. Warns about hostile
+    /// events between two units of the same side, which are looked up in the
+    /// given table.
+    pub fn is_hostile(&self, units: &UnitTable) -> bool {
+        if !self.typ().is_hostile() {
             return false;
         }
 
         // No two units of the same team may be involved in a hostile event
-        // XXX: Assumes, Players are friendly and npcs are hostile.
-        if let (Some(src), Some(tgt)) = (&self.source, &self.target) {
-            if src.hostile() == tgt.hostile() {
-                warn!("Detected hostile event on same side: {:?}", &self);
+        if let (Some(src), Some(tgt)) = (self.source(units), self.target(units)) {
+            if src.friendly() == tgt.friendly() {
+                warn!("Detected hostile event on same side: {:?}", self);
             }
         }
 
         true
     }
 
-    pub fn source(&self) -> Option<Unit> { self.source.clone() }
+    /// The columns of the line that the event does not keep, which are stored
+    /// in the unit table.
+    pub(crate) fn extras(&self) -> Option<ExtrasRef> { self.extras }
 
-    pub fn target(&self) -> Option<Unit> { self.target.clone() }
+    pub(crate) fn set_extras(&mut self, extras: Option<ExtrasRef>) { self.extras = extras; }
 
-    pub fn amount(&self) -> Option<u64> { self.amount }
+    /// Copy the columns of the line that the event does not keep to another
+    /// table, which its units are moved to.
+    pub(crate) fn move_extras(&mut self, from: &UnitTable, to: &mut UnitTable) {
+        self.extras = self
+            .extras
+            .and_then(|extras| to.add_extras(from.extras(extras)));
+    }
+
+    /// Replace the references to the units, after the units have been moved
+    /// to another table with the same start.
+    pub(crate) fn map_units<F: Fn(UnitRef) -> UnitRef>(&mut self, map: F) {
+        self.source = self.source.map(&map);
+        self.target = self.target.map(&map);
+    }
+
+    /// The reference to the source unit in the unit table of the log.
+    pub fn source_ref(&self) -> Option<UnitRef> { self.source }
+
+    /// The reference to the target unit in the unit table of the log.
+    pub fn target_ref(&self) -> Option<UnitRef> { self.target }
+
+    /// Look up the source unit in the unit table of the log this event belongs
+    /// to.
+    pub fn source<'a>(&self, units: &'a UnitTable) -> Option<&'a Unit> {
+        self.source.map(|unit| &units[unit])
+    }
+
+    /// Look up the target unit in the unit table of the log this event belongs
+    /// to.
+    pub fn target<'a>(&self, units: &'a UnitTable) -> Option<&'a Unit> {
+        self.target.map(|unit| &units[unit])
+    }
+
+    pub fn payload(&self) -> &Payload { &self.payload }
+
+    /// Many events have an amount of for instance damage or healing. In that
+    /// case, this will be set. For events like `EventType::UnitDied` this
+    /// will be `None`.
+    pub fn amount(&self) -> Option<u64> { self.payload.amount() }
 }
 
-impl FromStr for Event {
-    type Err = ParseError;
+impl Event {
+    /// Try to parse the event struct from an event string and return it. The
+    /// log does not contain the year, so the event is assumed to have
+    /// happened in the year provided. Its units are added to the table.
+    pub fn parse(s: &str, year: i32, units: &mut UnitTable) -> Result<Event, ParseError> {
+        Event::parse_with(s, year, LogFormat::Legacy, units)
+    }
 
-    /// Try to parse the event struct from an event string and return it.
-    /// Returns None if the string is not properly formatted.
-    fn from_str(s: &str) -> Result<Event, Self::Err> {
-        // Cut the later parts containing the advanced event information first,
+    /// Try to parse the event from a line of a log in the given format, like
+    /// it has been announced by the `COMBAT_LOG_VERSION` header of the log.
+    pub fn parse_with(
+        s: &str,
+        year: i32,
+        format: LogFormat,
+        units: &mut UnitTable
+    ) -> Result<Event, ParseError> {
+        Event::parse_recovering(s, format, units, false, |time| time.with_year(year))
+            .map(|(event, _)| event)
+    }
+
+    /// Parse the event like `parse_with`, moving its time into the right year
+    /// with `place` once the line turned out to be valid, which fails for
+    /// leap days in other years. When recovering, an event whose
+    /// fields following the units are malformed is kept with the fields that
+    /// could not be parsed as they are, and the error is returned alongside
+    /// it.
+    pub(crate) fn parse_recovering<P: FnOnce(NaiveDateTime) -> Option<NaiveDateTime>>(
+        s: &str,
+        format: LogFormat,
+        units: &mut UnitTable,
+        recover: bool,
+        place: P
+    ) -> Result<(Event, Option<ParseError>), ParseError> {
+        // Cut the later fields containing the advanced event information first,
         // because we have to cut by spaces afterwards, which would cut spell
         // names such as "Healing Stream Totem IX" into multiple parts
-        let parts: Vec<&str> = s.split(',').collect();
-
-        // Take the first element, which should contain the timestamp and the EventType.
-        let head: Vec<&str> = parts[0].split_whitespace().collect();
-        // Check if the Head is properly formatted.
-        if head.len() != 3 {
-            error!(
-                "Event head has incorrect length. Should be three, but found {}",
-                head.len()
-            );
-            return Err(ParseError::new(
-                ParseErrorType::WrongHeadLength,
-                head.len() - 1
-            ));
+        let fields = tokenize(s)?;
+        let (time, typ) = parse_head(&fields)?;
+
+        // Everything after the target depends on the type of the event, while
+        // the fields of unknown events are kept as they are.
+        let (payload, error) = if recover {
+            Payload::parse_recovering(&typ, &fields, format)
         }
+        else {
+            (Payload::parse(&typ, &fields, format)?, None)
+        };
 
-        // Read the time from the stamp. Have to use Naive, because the Timezone is not
-        // provided.
-        let time = match NaiveDateTime::parse_from_str(
-            &format!("{} {} 2019", head[0], head[1]),
-            "%m/%d %H:%M:%S%.3f %Y"
-        ) {
-            Ok(time) => time,
-            Err(err) => {
-                error!("Error while parsing the time: {}", err);
-                return Err(ParseError::new(ParseErrorType::WrongTimeFormat, 0));
-            }
+        // Read the source that this event was done by, or no cause, in case the
+        // event was part of the environment, and the target this event is
+        // affecting.
+        let (source, target) = match unit_cols(&typ, format) {
+            Some((source, target)) => (unit(&fields, source, units), unit(&fields, target, units)),
+            None => (None, None)
         };
 
-        // Last item in the head is the event type.
-        let typ = EventType::from_str(head[2], head[0].len() + head[1].len())?;
+        // Create the event from the parsed data, keeping the columns that the
+        // event does not keep otherwise in the unit table.
+        let time =
+            place(time).ok_or_else(|| ParseError::new(ParseErrorType::WrongTimeFormat, 0))?;
+        let cols = extra_cols(&fields, &typ, &payload, format);
+        let mut event = Event::new(time, typ, source, target, payload, units)?;
+        event.extras = units.add_extras(cols.into_iter().map(|col| fields[col].raw()));
+        Ok((event, error))
+    }
+}
 
-        // Read the source that this event was done by, or no cause, in case the
-        // event was part of the environment.
-        let source = Unit::from_raw(parts[1], parts[2]);
+impl FromStr for Event {
+    type Err = ParseError;
 
-        // Read the target this event is affecting. None is not an option here.
-        let target = Unit::from_raw(parts[4], parts[5]);
+    /// Try to parse the event struct from an event string and return it,
+    /// assuming it happened in the current year. Its units and the start of
+    /// the log its time is counted from go to a table of their own, which is
+    /// dropped, so use `Event::parse` if the units or the time are needed.
+    fn from_str(s: &str) -> Result<Event, Self::Err> {
+        Event::parse(s, Local::now().year(), &mut UnitTable::new())
+    }
+}
 
-        let amount = if typ.damaging() || typ.healing() {
-            match parts[10].parse() {
-                Ok(amount) => Some(amount),
-                Err(_) => return Err(ParseError::new(ParseErrorType::InvalidArg, 10))
-            }
+/// The year the time of an event is read in before it is placed in the year
+/// it happened in. It is a leap year, so that leap days can be read.
+const HEAD_YEAR: i32 = 2000;
+
+/// Read the time and the type of an event from the first field of its line,
+/// like `3/9 19:05:22.252  SPELL_DAMAGE`. The time is read in `HEAD_YEAR` and
+/// still has to be moved to the year of the log.
+pub(crate) fn parse_head(fields: &[Field]) -> Result<(NaiveDateTime, EventType), ParseError> {
+    // Take the first element, which should contain the timestamp and the EventType.
+    let head: Vec<&str> = fields[0].raw().split_whitespace().collect();
+    // Check if the Head is properly formatted.
+    if head.len() != 3 {
+        error!(
+            "Event head has incorrect length. Should be three, but found {}",
+            head.len()
+        );
+        return Err(ParseError::new(
+            ParseErrorType::WrongHeadLength,
+            head.len().saturating_sub(1)
+        ));
+    }
+
+    // Read the time from the stamp. Have to use Naive, because the Timezone is not
+    // provided.
+    let time = match NaiveDateTime::parse_from_str(
+        &format!("{} {} {}", head[0], head[1], HEAD_YEAR),
+        "%m/%d %H:%M:%S%.3f %Y"
+    ) {
+        Ok(time) => time,
+        Err(err) => {
+            error!("Error while parsing the time: {}", err);
+            return Err(ParseError::new(ParseErrorType::WrongTimeFormat, 0));
         }
-        else {
-            None
-        };
+    };
 
-        // Create the event from the parsed data
-        Ok(Event {
-            time,
-            typ,
-            source,
-            target,
-            amount
-        })
+    // Last item in the head is the event type.
+    let typ = EventType::from_str(head[2], head[0].len() + head[1].len())?;
+
+    Ok((time, typ))
+}
+
+/// The columns of the source and the target unit of an event of the given
+/// type. Events about the log or encounters have neither.
+pub(crate) fn unit_cols(typ: &EventType, format: LogFormat) -> Option<(usize, usize)> {
+    match typ {
+        EventType::CombatLogVersion | EventType::EncounterStart | EventType::EncounterEnd => None,
+        _ => Some((1, 1 + format.unit_fields()))
     }
 }
+
+/// Read the id, the field of the name and the flags of the unit starting at
+/// the given column, or `None` if there is no unit.
+pub(crate) fn unit_parts<'f, 'a>(
+    fields: &'f [Field<'a>],
+    col: usize
+) -> Option<(Guid, &'f Field<'a>, UnitFlags)> {
+    let guid = Guid::parse(fields.get(col)?.raw())?;
+    let name = fields.get(col + 1).filter(|name| !name.is_nil())?;
+    let flags = fields
+        .get(col + 2)?
+        .hex()
+        .and_then(|flags| u32::try_from(flags).ok())?;
+    Some((guid, name, UnitFlags::new(flags)))
+}
+
+/// Read the unit, whose id, name and flags start at the given column, and add
+/// it to the table.
+fn unit(fields: &[Field], col: usize, units: &mut UnitTable) -> Option<UnitRef> {
+    let (guid, name, flags) = unit_parts(fields, col)?;
+    Some(units.intern_parts(guid, name.str()?, flags))
+}
@@ -1,29 +1,109 @@
-use crate::{Event, Unit};
+use crate::{Event, EventType, ResolvedEvent, Unit, UnitTable};
+use std::collections::{HashMap, HashSet};
 
-pub fn damage_dealt<'a, E>(src: &Unit, events: E) -> u64
+/// An event whose units can be looked up, which is what the functions of this
+/// module work on. The events of a log are paired with its unit table, like
+/// `Log::resolved` does, while `EventReader::resolved` streams the events of
+/// a log together with their units, so that the log never has to be held in
+/// memory.
+pub trait Resolved {
+    fn event(&self) -> &Event;
+
+    fn source(&self) -> Option<&Unit>;
+
+    fn target(&self) -> Option<&Unit>;
+}
+
+impl Resolved for (&Event, &UnitTable) {
+    fn event(&self) -> &Event { self.0 }
+
+    fn source(&self) -> Option<&Unit> { self.0.source(self.1) }
+
+    fn target(&self) -> Option<&Unit> { self.0.target(self.1) }
+}
+
+impl Resolved for ResolvedEvent {
+    fn event(&self) -> &Event { self.event() }
+
+    fn source(&self) -> Option<&Unit> { self.source() }
+
+    fn target(&self) -> Option<&Unit> { self.target() }
+}
+
+/// Sum up the damage the unit has dealt. Events whose amount could not be
+/// read, like those kept in `ParseMode::BestEffort`, are skipped.
+pub fn damage_dealt<E>(src: &Unit, events: E) -> u64
 where
-    E: Iterator<Item = &'a Event>
+    E: IntoIterator,
+    E::Item: Resolved
 {
     let mut damage = 0;
     for e in events {
-        if e.source().as_ref() == Some(src) && e.typ().damaging() {
-            damage += e.amount().expect("Damaging event does not have amount");
+        if e.event().typ().damaging() && e.source() == Some(src) {
+            if let Some(amount) = e.event().amount() {
+                damage += amount;
+            }
         }
     }
 
     damage
 }
 
-pub fn healing_done<'a, E>(src: &Unit, events: E) -> u64
+/// Sum up the healing the unit has done, skipping events without an amount
+/// like `damage_dealt`.
+pub fn healing_done<E>(src: &Unit, events: E) -> u64
 where
-    E: Iterator<Item = &'a Event>
+    E: IntoIterator,
+    E::Item: Resolved
 {
     let mut healing = 0;
     for e in events {
-        if e.source().as_ref() == Some(src) && e.typ().healing() {
-            healing += e.amount().expect("Healing event does not have amount");
+        if e.event().typ().healing() && e.source() == Some(src) {
+            if let Some(amount) = e.event().amount() {
+                healing += amount;
+            }
         }
     }
 
     healing
 }
+
+/// Collect all spawns of the creature template with the given entry id that
+/// take part in any of the events.
+pub fn spawns<E>(entry: u32, events: E) -> HashSet<Unit>
+where
+    E: IntoIterator,
+    E::Item: Resolved
+{
+    let mut spawns = HashSet::new();
+    for e in events {
+        for unit in e.source().into_iter().chain(e.target()) {
+            if unit.entry() == Some(entry) && !spawns.contains(unit) {
+                spawns.insert(unit.clone());
+            }
+        }
+    }
+
+    spawns
+}
+
+/// Count how many units of each creature template have died, by their entry
+/// id.
+pub fn kills_per_entry<E>(events: E) -> HashMap<u32, u64>
+where
+    E: IntoIterator,
+    E::Item: Resolved
+{
+    let mut kills = HashMap::new();
+    for e in events {
+        if e.event().typ() != EventType::UnitDied {
+            continue;
+        }
+
+        if let Some(entry) = e.target().and_then(Unit::entry) {
+            *kills.entry(entry).or_insert(0) += 1;
+        }
+    }
+
+    kills
+}
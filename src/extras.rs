@@ -0,0 +1,193 @@
+//! The columns of a line that its event does not keep, like the raid flags of
+//! the units and the advanced fields of versioned logs. They are kept exactly
+//! as they have been written in the log, so that the event can be written
+//! back into the very same line.
+
+use crate::event::{unit_cols, EventType, Prefix};
+use crate::format::LogFormat;
+use crate::payload::{param_cols, Payload, PrefixParams, SuffixParams};
+use crate::tokenizer::Field;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::num::NonZeroU32;
+
+/// Refers to the extra columns of an event in the `UnitTable` of its log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ExtrasRef(NonZeroU32);
+
+/// The extra columns of all events of a log. The columns of an event are
+/// stored one after another, each followed by a line break, which no field
+/// of a line can contain.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Extras {
+    text: String,
+    /// The end of the columns of each event in the text.
+    ends: Vec<usize>
+}
+
+impl ExtrasRef {
+    fn new(index: usize) -> ExtrasRef {
+        let index = u32::try_from(index + 1).expect("Too many events in the log");
+        ExtrasRef(NonZeroU32::new(index).unwrap())
+    }
+
+    fn index(&self) -> usize { self.0.get() as usize - 1 }
+}
+
+impl Extras {
+    /// Keep the columns of an event, or nothing if there are none.
+    pub fn push<'a, I: IntoIterator<Item = &'a str>>(&mut self, cols: I) -> Option<ExtrasRef> {
+        let start = self.text.len();
+        for col in cols {
+            self.text.push_str(col);
+            self.text.push('\n');
+        }
+        if self.text.len() == start {
+            return None;
+        }
+
+        self.ends.push(self.text.len());
+        Some(ExtrasRef::new(self.ends.len() - 1))
+    }
+
+    /// The columns of an event in the order they are written in the line.
+    /// Panics if the reference belongs to another log.
+    pub fn get(&self, extras: ExtrasRef) -> impl Iterator<Item = &str> + '_ {
+        let index = extras.index();
+        let start = match index {
+            0 => 0,
+            _ => self.ends[index - 1]
+        };
+        self.text[start..self.ends[index] - 1].split('\n')
+    }
+
+    /// The columns of all events in order.
+    #[cfg(feature = "serde")]
+    pub fn all(&self) -> impl Iterator<Item = Vec<&str>> + '_ {
+        (0..self.ends.len()).map(move |i| self.get(ExtrasRef::new(i)).collect())
+    }
+}
+
+/// The number of columns the payload of an event is written in, starting at
+/// the first column of its parameters, or `None` if all fields of the line
+/// are kept by the payload or cannot be told apart from it. Raw fields are
+/// all fields up to the end of the line.
+fn payload_cols(payload: &Payload, format: LogFormat) -> Option<usize> {
+    let versioned = (format != LogFormat::Legacy) as usize;
+    let base = format.base_amount() as usize;
+    Some(match payload {
+        Payload::Empty => 0,
+        Payload::Combat { prefix, suffix } => {
+            let prefix = match prefix {
+                PrefixParams::None => 0,
+                PrefixParams::Spell(_) => 3,
+                PrefixParams::Environmental(_) => 1
+            };
+            let suffix = match suffix {
+                SuffixParams::None => 0,
+                SuffixParams::Damage(_) => 9 + base,
+                SuffixParams::Missed(miss) => 1 + versioned + miss.amount().is_some() as usize,
+                SuffixParams::Heal(_) => 4 + base,
+                SuffixParams::Energize { .. } => 2 + versioned,
+                SuffixParams::Drain { .. } | SuffixParams::ExtraSpell { .. } => 3,
+                SuffixParams::Dispel { .. } => 4,
+                SuffixParams::ExtraAttacks { .. } | SuffixParams::CastFailed { .. } => 1,
+                SuffixParams::Aura { stacks, .. } => 1 + stacks.is_some() as usize,
+                SuffixParams::Raw(fields) => fields.len()
+            };
+            prefix + suffix
+        }
+        Payload::Enchant(_) => 3,
+        Payload::EncounterStart(start) => 4 + start.instance().is_some() as usize,
+        Payload::EncounterEnd(end) => 5 + end.fight_time().is_some() as usize,
+        Payload::Raw(fields) => fields.len(),
+        Payload::Version(_) | Payload::Unknown(_) => return None
+    })
+}
+
+/// The columns of the parameters of a suffix that its payload does not keep,
+/// relative to the first column of the parameters. Versioned logs add the
+/// base amount of damage and healing, the overkill of damage, which is `-1`
+/// instead of zero when there is none, the flag for off hand attacks of
+/// misses and the wasted power of energizing events.
+fn suffix_extras(suffix: &SuffixParams, format: LogFormat) -> Vec<usize> {
+    if format == LogFormat::Legacy {
+        return Vec::new();
+    }
+
+    let base = format.base_amount() as usize;
+    match suffix {
+        SuffixParams::Damage(_) => (1..=1 + base).collect(),
+        SuffixParams::Heal(_) => (1..1 + base).collect(),
+        SuffixParams::Missed(_) | SuffixParams::Energize { .. } => vec![1],
+        _ => Vec::new()
+    }
+}
+
+/// The columns of a line that the event parsed from it does not keep, in the
+/// order they are written in.
+pub(crate) fn extra_cols(
+    fields: &[Field],
+    typ: &EventType,
+    payload: &Payload,
+    format: LogFormat
+) -> Vec<usize> {
+    let payload_len = match payload_cols(payload, format) {
+        Some(len) => len,
+        None => return Vec::new()
+    };
+
+    // The raid flags follow the flags of the units.
+    let mut cols = Vec::new();
+    let mut payload_col = 1;
+    if let Some((source, target)) = unit_cols(typ, format) {
+        payload_col = format.payload_col();
+        if format != LogFormat::Legacy {
+            cols.extend([source + 3, target + 3]);
+        }
+    }
+
+    if let (EventType::Combat(prefix, suffix), Payload::Combat { suffix: params, .. }) =
+        (typ, payload)
+    {
+        let (prefix_col, suffix_col) = param_cols(*prefix, *suffix, format);
+        let advanced = format.advanced_fields(*suffix);
+        let advanced_col = match prefix {
+            Prefix::Environmental => prefix_col - advanced,
+            _ => suffix_col - advanced
+        };
+        cols.extend(advanced_col..advanced_col + advanced);
+        cols.extend(
+            suffix_extras(params, format)
+                .into_iter()
+                .map(|col| suffix_col + col)
+        );
+        payload_col += advanced;
+    }
+
+    // Everything after the payload is kept as well, while lines that end
+    // early have only some of the columns.
+    cols.extend(payload_col + payload_len..fields.len());
+    cols.retain(|&col| col < fields.len());
+    cols
+}
+
+/// Serialized as the index of the columns in the table, like `UnitRef`.
+#[cfg(feature = "serde")]
+impl Serialize for ExtrasRef {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.index() as u64)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for ExtrasRef {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<ExtrasRef, D::Error> {
+        let index = u32::deserialize(deserializer)?;
+        if index == u32::MAX {
+            return Err(serde::de::Error::custom("extras index out of range"));
+        }
+        Ok(ExtrasRef::new(index as usize))
+    }
+}
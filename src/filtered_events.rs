@@ -22,7 +22,7 @@ impl<'a> Filterable for FilteredEvents<'a> {
             // If the check of the filter fails, the bit in the including filter must be set
             // to false, regardless of what it was before. include[i] is checked,
             // because it is usually much faster than the filters check.
-            if self.include[i] && !by.check(&event) {
+            if self.include[i] && !by.check(event) {
                 self.include.set(i, false);
             }
         }
@@ -36,7 +36,7 @@ impl<'a> Filterable for FilteredEvents<'a> {
             // set to true, regardless of what it was before. include[i] is
             // checked, because it is usually much faster than the filters
             // check.
-            if !self.include[i] && by.check(&event) {
+            if !self.include[i] && by.check(event) {
                 self.include.set(i, true);
             }
         }
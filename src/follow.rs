@@ -0,0 +1,220 @@
+//! Following a log file while the client is still writing to it, to get the
+//! events of a raid while it is in progress.
+
+use crate::log::ParseError;
+use crate::parser::{EventParser, ParseOptions};
+use crate::unit::Unit;
+use crate::unit_table::UnitTable;
+use crate::Event;
+use chrono::NaiveDateTime;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Watches a log file and parses every line that is appended to it. Lines are
+/// only parsed once they are complete. When the file is truncated or replaced
+/// by a new one, it is read again from the start.
+pub struct LogFollower {
+    path: PathBuf,
+    options: ParseOptions,
+    parser: EventParser,
+    file: Option<File>,
+    /// The position in the file up to which it has been read.
+    pos: u64,
+    /// The start of a line that has not been terminated yet.
+    partial: Vec<u8>,
+    line_number: usize
+}
+
+/// An event sent by a follower running on its own thread. It comes with its
+/// time and units, since the unit table of the follower stays on its thread.
+#[derive(Clone, Debug)]
+pub struct FollowedEvent {
+    event:  Event,
+    time:   NaiveDateTime,
+    source: Option<Unit>,
+    target: Option<Unit>
+}
+
+/// Something that identifies a file, even when it has been renamed, so it can
+/// be detected when the log has been replaced.
+#[cfg(unix)]
+fn identity(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_meta: &Metadata) -> Option<(u64, u64)> { None }
+
+impl LogFollower {
+    /// Follow the log at the given path from its start. The file does not have
+    /// to exist yet.
+    pub fn new<P: AsRef<Path>>(path: P, options: &ParseOptions) -> LogFollower {
+        LogFollower {
+            path: path.as_ref().to_path_buf(),
+            options: options.clone(),
+            parser: EventParser::new(options),
+            file: None,
+            pos: 0,
+            partial: Vec::new(),
+            line_number: 0
+        }
+    }
+
+    /// Follow the log at the given path, skipping everything that has been
+    /// written to it so far.
+    pub fn from_end<P: AsRef<Path>>(path: P, options: &ParseOptions) -> io::Result<LogFollower> {
+        let mut follower = LogFollower::new(path, options);
+        let mut file = File::open(&follower.path)?;
+        follower.pos = file.seek(SeekFrom::End(0))?;
+        follower.file = Some(file);
+        Ok(follower)
+    }
+
+    /// The number of complete lines that have been read from the current file.
+    pub fn line_number(&self) -> usize { self.line_number }
+
+    /// The units of all events that have been read, even from files that have
+    /// been replaced since.
+    pub fn units(&self) -> &UnitTable { self.parser.units() }
+
+    /// Start reading the file from the beginning, because it has been
+    /// truncated or replaced.
+    fn restart(&mut self) {
+        info!("Log file {:?} has been truncated or replaced", self.path);
+        self.parser.restart(&self.options);
+        self.file = None;
+        self.pos = 0;
+        self.partial.clear();
+        self.line_number = 0;
+    }
+
+    /// Read everything that has been appended since the last poll and pass the
+    /// parsed events to the callback, together with the units they refer to.
+    /// Returns the number of lines that have been passed. If the file does not
+    /// exist (yet), nothing happens.
+    pub fn poll<F>(&mut self, mut callback: F) -> io::Result<usize>
+    where
+        F: FnMut(Result<Event, ParseError>, &UnitTable)
+    {
+        let meta = match fs::metadata(&self.path) {
+            Ok(meta) => meta,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err)
+        };
+
+        if let Some(file) = &self.file {
+            if meta.len() < self.pos || identity(&meta) != identity(&file.metadata()?) {
+                self.restart();
+            }
+        }
+
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => File::open(&self.path)?
+        };
+
+        let mut appended = Vec::new();
+        file.seek(SeekFrom::Start(self.pos))?;
+        self.pos += file.read_to_end(&mut appended)? as u64;
+        self.file = Some(file);
+
+        self.partial.extend_from_slice(&appended);
+        let complete = match self.partial.iter().rposition(|&b| b == b'\n') {
+            Some(end) => end + 1,
+            None => return Ok(0)
+        };
+
+        // The position of the first line, which may have been started before
+        // the last poll.
+        let mut offset = self.pos - self.partial.len() as u64;
+        let mut lines = 0;
+        for line in self.partial[..complete - 1].split(|&b| b == b'\n') {
+            let start = offset;
+            offset += line.len() as u64 + 1;
+            self.line_number += 1;
+
+            let line = String::from_utf8_lossy(line);
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+
+            lines += 1;
+            let line_number = self.line_number;
+            let event = self.parser.parse_line(line).map(|mut event| {
+                event.set_position(line_number, start);
+                event
+            });
+            callback(event, self.parser.units());
+        }
+
+        self.partial.drain(..complete);
+        Ok(lines)
+    }
+
+    /// Keep polling the file in the given interval, until the callback returns
+    /// `false`. Errors while reading are passed to the callback as well, after
+    /// which the follower keeps on trying.
+    pub fn follow<F>(&mut self, interval: Duration, mut callback: F)
+    where
+        F: FnMut(Result<Event, ParseError>, &UnitTable) -> bool
+    {
+        let mut running = true;
+        while running {
+            let polled = self.poll(|event, units| {
+                if running {
+                    running = callback(event, units);
+                }
+            });
+
+            if let Err(err) = polled {
+                running = callback(Err(err.into()), self.units());
+            }
+
+            if running {
+                thread::sleep(interval);
+            }
+        }
+    }
+
+    /// Follow the file on its own thread, sending the events together with
+    /// their units through the returned channel. The thread stops with the
+    /// first event that arrives after the receiver has been dropped.
+    pub fn spawn(
+        mut self,
+        interval: Duration
+    ) -> (Receiver<Result<FollowedEvent, ParseError>>, JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            self.follow(interval, |event, units| {
+                let event = event.map(|event| FollowedEvent {
+                    time: event.time(units),
+                    source: event.source(units).cloned(),
+                    target: event.target(units).cloned(),
+                    event
+                });
+                sender.send(event).is_ok()
+            });
+        });
+
+        (receiver, handle)
+    }
+}
+
+impl FollowedEvent {
+    /// The event, whose unit references refer to the table of the follower.
+    pub fn event(&self) -> &Event { &self.event }
+
+    pub fn time(&self) -> NaiveDateTime { self.time }
+
+    pub fn source(&self) -> Option<&Unit> { self.source.as_ref() }
+
+    pub fn target(&self) -> Option<&Unit> { self.target.as_ref() }
+
+    pub fn into_event(self) -> Event { self.event }
+}
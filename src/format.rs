@@ -0,0 +1,115 @@
+//! The layouts of the lines of a log, which have changed between the versions
+//! of the client. Logs of clients up to Wrath of the Lich King have no header,
+//! while later clients start every log with a `COMBAT_LOG_VERSION` line.
+
+use crate::event::Suffix;
+use crate::log::{ParseError, ParseErrorType};
+use crate::tokenizer::Field;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// The layout of the fields of the events of a log.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum LogFormat {
+    /// The layout of clients up to Wrath of the Lich King, like the logs in
+    /// `logs/`.
+    #[default]
+    Legacy,
+    /// The layout announced by a `COMBAT_LOG_VERSION` header.
+    Versioned {
+        version:  u32,
+        /// With advanced logging enabled, events about damage, healing,
+        /// power and casts carry additional information about their source.
+        advanced: bool
+    }
+}
+
+/// The number of advanced fields, starting with the guid of the unit they
+/// describe and ending with its level.
+const ADVANCED_FIELDS: usize = 17;
+
+/// The first version that writes the base amount following the amount of
+/// damage and healing events.
+const BASE_AMOUNT_VERSION: u32 = 20;
+
+impl LogFormat {
+    /// Read the format from the fields of a `COMBAT_LOG_VERSION` line, like
+    /// `COMBAT_LOG_VERSION,20,ADVANCED_LOG_ENABLED,1,...`.
+    pub fn from_header(fields: &[Field]) -> Result<LogFormat, ParseError> {
+        let version = fields
+            .get(1)
+            .and_then(Field::int)
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| ParseError::new(ParseErrorType::InvalidArg, 1))?;
+
+        let advanced = match fields
+            .iter()
+            .position(|f| f.raw() == "ADVANCED_LOG_ENABLED")
+        {
+            Some(col) => fields.get(col + 1).and_then(Field::int) == Some(1),
+            None => false
+        };
+
+        Ok(LogFormat::Versioned { version, advanced })
+    }
+
+    /// The version from the header, or `None` for logs without one.
+    pub fn version(&self) -> Option<u32> {
+        match self {
+            LogFormat::Legacy => None,
+            LogFormat::Versioned { version, .. } => Some(*version)
+        }
+    }
+
+    pub fn advanced(&self) -> bool {
+        match self {
+            LogFormat::Legacy => false,
+            LogFormat::Versioned { advanced, .. } => *advanced
+        }
+    }
+
+    /// The number of fields describing a unit. These are its id, name and
+    /// flags, followed by its raid flags in versioned logs.
+    pub fn unit_fields(&self) -> usize {
+        match self {
+            LogFormat::Legacy => 3,
+            LogFormat::Versioned { .. } => 4
+        }
+    }
+
+    /// The column where the payload starts, after the source and target
+    /// units.
+    pub fn payload_col(&self) -> usize { 1 + 2 * self.unit_fields() }
+
+    /// The number of advanced fields of a combat event. They follow the
+    /// prefix parameters, except for environmental events, where they come
+    /// right after the units.
+    pub fn advanced_fields(&self, suffix: Suffix) -> usize {
+        if !self.advanced() {
+            return 0;
+        }
+
+        match suffix {
+            Suffix::Damage
+            | Suffix::Heal
+            | Suffix::Energize
+            | Suffix::Drain
+            | Suffix::Leech
+            | Suffix::CastSuccess => ADVANCED_FIELDS,
+            _ => 0
+        }
+    }
+
+    /// Check if the amount of damage and healing is followed by the amount
+    /// before any modifiers have been applied.
+    pub fn base_amount(&self) -> bool {
+        self.version()
+            .is_some_and(|version| version >= BASE_AMOUNT_VERSION)
+    }
+}
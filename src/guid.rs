@@ -0,0 +1,149 @@
+//! Decoding of the globally unique ids of units. The upper bits of an id tell
+//! what kind of unit it belongs to, and for NPCs, which creature template it
+//! has been spawned from. Clients after Wrath of the Lich King write the same
+//! information as text, like `Creature-0-3019-2450-28297-175732-00001C2F1B`.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// The kind of unit a `Guid` belongs to, as encoded in its highest bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GuidType {
+    Player,
+    Creature,
+    Pet,
+    Vehicle,
+    Object,
+    /// Any other high type, which does not appear for units in the log.
+    Other(u16),
+    /// Any other type of a textual id, like `Item`.
+    Unknown
+}
+
+/// The id of a unit as it is recorded in the log, e.g. `0xF130005E49000018`
+/// or `Player-1234-0ABCDEF`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Guid {
+    /// The 64 bit ids written by clients up to Wrath of the Lich King.
+    Legacy(u64),
+    /// The textual ids of later clients, kept exactly as they were written.
+    Text(Arc<str>)
+}
+
+impl Guid {
+    pub fn new(raw: u64) -> Guid { Guid::Legacy(raw) }
+
+    /// Read an id as it is written in the log. Returns `None` for the empty
+    /// ids of missing units, like `0x0000000000000000`, and anything that is
+    /// not an id at all.
+    pub fn parse(s: &str) -> Option<Guid> {
+        if let Some(hex) = s.strip_prefix("0x") {
+            u64::from_str_radix(hex, 16)
+                .ok()
+                .filter(|&raw| raw != 0)
+                .map(Guid::Legacy)
+        }
+        else if s.starts_with(|c: char| c.is_ascii_alphabetic()) && s.contains('-') {
+            Some(Guid::Text(s.into()))
+        }
+        else {
+            None
+        }
+    }
+
+    /// The 64 bit value of the id, if it is a legacy one.
+    pub fn raw(&self) -> Option<u64> {
+        match self {
+            Guid::Legacy(raw) => Some(*raw),
+            Guid::Text(_) => None
+        }
+    }
+
+    /// The highest 16 bits of a legacy id, like `0xF130` for creatures.
+    pub fn high(&self) -> Option<u16> { self.raw().map(|raw| (raw >> 48) as u16) }
+
+    /// The dash separated parts of a textual id.
+    fn part(&self, index: usize) -> Option<&str> {
+        match self {
+            Guid::Legacy(_) => None,
+            Guid::Text(text) => text.split('-').nth(index)
+        }
+    }
+
+    pub fn typ(&self) -> GuidType {
+        match self {
+            Guid::Legacy(raw) => match (raw >> 48) as u16 {
+                0x0000 => GuidType::Player,
+                0xF130 => GuidType::Creature,
+                0xF140 => GuidType::Pet,
+                0xF150 => GuidType::Vehicle,
+                0xF110 => GuidType::Object,
+                other => GuidType::Other(other)
+            },
+            Guid::Text(_) => match self.part(0) {
+                Some("Player") => GuidType::Player,
+                Some("Creature") => GuidType::Creature,
+                Some("Pet") => GuidType::Pet,
+                Some("Vehicle") => GuidType::Vehicle,
+                Some("GameObject") => GuidType::Object,
+                _ => GuidType::Unknown
+            }
+        }
+    }
+
+    /// The id of the template the unit has been spawned from, like `0x5E49`
+    /// for Dark Ranger Marrah. Only creatures, vehicles and objects have
+    /// one.
+    pub fn entry(&self) -> Option<u32> {
+        match self.typ() {
+            GuidType::Creature | GuidType::Vehicle | GuidType::Object => match self {
+                Guid::Legacy(raw) => Some(((raw >> 24) & 0xFF_FFFF) as u32),
+                Guid::Text(_) => self.part(5)?.parse().ok()
+            },
+            _ => None
+        }
+    }
+
+    /// The counter that distinguishes multiple spawns of the same template,
+    /// or multiple summons of pets. Players do not have one.
+    pub fn spawn_serial(&self) -> Option<u64> {
+        match (self, self.typ()) {
+            (_, GuidType::Player) => None,
+            (Guid::Legacy(raw), _) => Some(raw & 0xFF_FFFF),
+            (Guid::Text(_), _) => u64::from_str_radix(self.part(6)?, 16).ok()
+        }
+    }
+}
+
+/// Ids are serialized as they are written in the log, like
+/// `"0xF130005E49000018"` or `"Player-1234-0ABCDEF"`.
+#[cfg(feature = "serde")]
+impl Serialize for Guid {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Guid {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Guid, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16)
+                .map(Guid::Legacy)
+                .map_err(serde::de::Error::custom),
+            None => Guid::parse(&s).ok_or_else(|| serde::de::Error::custom("invalid guid"))
+        }
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Guid::Legacy(raw) => write!(f, "0x{:016X}", raw),
+            Guid::Text(text) => write!(f, "{}", text)
+        }
+    }
+}
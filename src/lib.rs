@@ -1,22 +1,58 @@
 #[macro_use]
 extern crate log as logger;
 
+pub mod anonymize;
+pub mod cache;
+pub mod compression;
 pub mod encounter;
 pub mod event;
 pub mod extract;
+mod extras;
 pub mod filter;
 pub mod filtered_events;
+pub mod follow;
+pub mod format;
+pub mod guid;
 pub mod log;
+pub mod mapped;
 pub mod math;
+pub mod merge;
+mod parallel;
+pub mod parser;
+pub mod payload;
+pub mod reader;
+pub mod session;
+pub mod tokenizer;
 pub mod unit;
+pub mod unit_flags;
+pub mod unit_table;
+pub mod validate;
+pub mod writer;
 
 pub use crate::log::*;
+pub use anonymize::*;
+pub use cache::CACHE_VERSION;
+pub use compression::Compression;
 pub use encounter::*;
 pub use event::*;
 pub use filter::*;
 pub use filtered_events::*;
+pub use follow::*;
+pub use format::*;
+pub use guid::*;
+pub use mapped::*;
 pub use math::*;
+pub use merge::*;
+pub use parser::{ParseMode, ParseOptions};
+pub use payload::*;
+pub use reader::*;
+pub use session::*;
+pub use tokenizer::*;
 pub use unit::*;
+pub use unit_flags::*;
+pub use unit_table::*;
+pub use validate::*;
+pub use writer::*;
 
 #[cfg(test)]
 extern crate env_logger;
@@ -24,9 +60,10 @@ extern crate env_logger;
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashSet;
+    use chrono::Datelike;
+    use std::collections::{HashMap, HashSet};
     use std::fs::File;
-    use std::io::Read;
+    use std::io::{BufReader, Write};
     use std::str::FromStr;
     use std::sync::Once;
 
@@ -41,7 +78,1510 @@ mod tests {
     fn load_event() {
         env_init();
 
-        Event::from_str("3/9 19:05:22.252  SPELL_CAST_SUCCESS,0x000000000014EABC,\"Draleofdeath\",0x512,0x000000000014EABC,\"Draleofdeath\",0x512,25899,\"Greater Blessing of Sanctuary\",0x2").expect("Unable to parse event");
+        let mut units = UnitTable::new();
+        Event::parse("3/9 19:05:22.252  SPELL_CAST_SUCCESS,0x000000000014EABC,\"Draleofdeath\",0x512,0x000000000014EABC,\"Draleofdeath\",0x512,25899,\"Greater Blessing of Sanctuary\",0x2", 2020, &mut units).expect("Unable to parse event");
+
+        let event = Event::from_str("3/9 19:05:22.252  SPELL_CAST_SUCCESS,0x000000000014EABC,\"Draleofdeath\",0x512,0x000000000014EABC,\"Draleofdeath\",0x512,25899,\"Greater Blessing of Sanctuary\",0x2").expect("Unable to parse event");
+        assert_eq!(Some(25899), event.payload().spell().map(|spell| spell.id()));
+    }
+
+    #[test]
+    fn load_event_payload() {
+        env_init();
+
+        let mut units = UnitTable::new();
+        let event = Event::parse("3/9 19:06:08.568  RANGE_DAMAGE,0x00000000001402ED,\"Arthurobob\",0x512,0xF13000744E0000BD,\"Spitting Cobra\",0xa48,75,\"Auto Shot\",0x1,1704,0,1,0,0,0,1,nil,nil", 2020, &mut units).expect("Unable to parse event");
+
+        let spell = event.payload().spell().expect("Event has no spell");
+        assert_eq!(75, spell.id());
+        assert_eq!("Auto Shot", spell.name());
+        assert_eq!(0x1, spell.school());
+
+        let damage = event.payload().damage().expect("Event has no damage");
+        assert_eq!(1704, damage.amount());
+        assert!(damage.critical());
+        assert!(!damage.glancing());
+        assert_eq!(Some(1704), event.amount());
+    }
+
+    #[test]
+    fn load_event_columns() {
+        env_init();
+
+        let mut units = UnitTable::new();
+        let swing = Event::parse("4/14 11:50:16.122  SWING_DAMAGE,0xF130005E49000018,\"Dark Ranger Marrah\",0xa48,0x00000000000E8806,\"Milune\",0x512,317,0,1,0,0,0,nil,nil,nil", 2020, &mut units).expect("Unable to parse event");
+        assert_eq!(
+            EventType::Combat(Prefix::Swing, Suffix::Damage),
+            swing.typ()
+        );
+        assert!(swing.payload().spell().is_none());
+        assert_eq!(Some(317), swing.amount());
+
+        let environmental = Event::parse("3/9 19:05:49.583  ENVIRONMENTAL_DAMAGE,0x0000000000000000,nil,0x80000000,0x00000000001402ED,\"Arthurobob\",0x512,FALLING,4792,0,1,0,0,0,nil,nil,nil", 2020, &mut units).expect("Unable to parse event");
+        assert_eq!(Some(4792), environmental.amount());
+
+        let periodic = Event::parse("4/5 14:28:05.575  SPELL_PERIODIC_MISSED,0x000000000013B13C,\"Nundo\",0x512,0xF130005D94000096,\"Dragonflayer Strategist\",0xa48,42926,\"Flamestrike\",0x4,ABSORB,566", 2020, &mut units).expect("Unable to parse event");
+        assert_eq!(
+            EventType::Combat(Prefix::SpellPeriodic, Suffix::Missed),
+            periodic.typ()
+        );
+        assert_eq!(
+            &Payload::Combat {
+                prefix: PrefixParams::Spell(Spell::new(42926, "Flamestrike".into(), 0x4)),
+                suffix: SuffixParams::Missed(Miss::new(MissType::Absorb, Some(566)))
+            },
+            periodic.payload()
+        );
+
+        let dose = Event::parse("4/14 11:54:14.138  SPELL_AURA_REMOVED_DOSE,0x000000000015A6D4,\"Ikiharu\",0x511,0x000000000015A6D4,\"Ikiharu\",0x511,55166,\"Tidal Force\",0x1,BUFF,1", 2020, &mut units).expect("Unable to parse event");
+        assert_eq!(
+            EventType::Combat(Prefix::Spell, Suffix::AuraRemovedDose),
+            dose.typ()
+        );
+    }
+
+    #[test]
+    fn load_event_flags() {
+        env_init();
+
+        let mut units = UnitTable::new();
+        let event = Event::parse("3/9 19:05:36.745  SPELL_PERIODIC_HEAL,0xF1300079D10000E5,\"Healing Stream Totem IX\",0x1111,0x000000000013410C,\"Vargni\",0x10512,52042,\"Healing Stream Totem\",0x8,346,0,0,nil", 2020, &mut units).expect("Unable to parse event");
+
+        let totem = event.source(&units).expect("Event has no source").flags();
+        assert_eq!(Some(Affiliation::Mine), totem.affiliation());
+        assert_eq!(Some(Reaction::Friendly), totem.reaction());
+        assert_eq!(Some(Control::Player), totem.control());
+        assert_eq!(Some(UnitType::Pet), totem.unit_type());
+
+        let target = event.target(&units).expect("Event has no target");
+        assert_eq!(Some(Affiliation::Party), target.flags().affiliation());
+        assert!(target.is_player());
+        assert!(target.friendly());
+        assert!(!target.hostile());
+
+        let marrah = Event::parse("4/14 11:50:16.122  SWING_DAMAGE,0xF130005E49000018,\"Dark Ranger Marrah\",0xa48,0x00000000000E8806,\"Milune\",0x512,317,0,1,0,0,0,nil,nil,nil", 2020, &mut units).expect("Unable to parse event").source(&units).expect("Event has no source");
+        assert!(!marrah.is_player());
+        assert!(marrah.hostile());
+        assert_eq!(Some(UnitType::Npc), marrah.flags().unit_type());
+        assert_eq!(Some(Control::Npc), marrah.flags().control());
+    }
+
+    #[test]
+    fn guid_spawns() {
+        env_init();
+
+        let marrah = Guid::new(0xF130005E49000018);
+        assert_eq!(GuidType::Creature, marrah.typ());
+        assert_eq!(Some(0x5E49), marrah.entry());
+        assert_eq!(Some(0x18), marrah.spawn_serial());
+        assert_eq!(GuidType::Player, Guid::new(0xE8806).typ());
+        assert_eq!(None, Guid::new(0xE8806).entry());
+
+        let log = Log::read_file("logs/turm_utgarde.txt").expect("Unable to read log");
+
+        let ghouls = extract::spawns(0x5E14, log.resolved());
+        assert_eq!(14, ghouls.len());
+        assert!(ghouls.iter().all(|u| u.name() == "Tunneling Ghoul"));
+
+        let kills = extract::kills_per_entry(log.resolved());
+        assert_eq!(Some(&14), kills.get(&0x5E14));
+    }
+
+    #[test]
+    fn new_year_rollover() {
+        env_init();
+
+        let log = "12/31 23:59:59.900  UNIT_DIED,0x0000000000000000,nil,0x80000000,0xF1300073BE000071,\"Fanged Pit Viper\",0xa28\n1/1 00:00:00.100  UNIT_DIED,0x0000000000000000,nil,0x80000000,0xF13000744E0000BD,\"Spitting Cobra\",0xa48";
+
+        let (events, units, report) =
+            parser::parse_with(log, &ParseOptions::new().year(2019)).unwrap();
+        assert!(report.is_empty());
+        assert_eq!(2, events.len());
+        assert_eq!(2019, events[0].time(&units).year());
+        assert_eq!(2020, events[1].time(&units).year());
+        assert!(events[0].time(&units) < events[1].time(&units));
+    }
+
+    #[test]
+    fn leap_day() {
+        env_init();
+
+        // The log has been written in 2020, but the file was last modified in
+        // the year after, which is no leap year.
+        let text = "2/29 23:59:59.900  UNIT_DIED,0x0000000000000000,nil,0x80000000,0xF1300073BE000071,\"Fanged Pit Viper\",0xa28\n3/1 00:00:00.100  UNIT_DIED,0x0000000000000000,nil,0x80000000,0xF13000744E0000BD,\"Spitting Cobra\",0xa48";
+        let path = std::env::temp_dir().join(format!("leap_day_{}.txt", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let modified = chrono::NaiveDate::from_ymd_opt(2021, 1, 5)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_local_timezone(chrono::Local)
+            .unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified.into())
+            .unwrap();
+        let read = Log::read_file(&path);
+        let mapped = unsafe { MappedLog::open(&path) }.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let log = read.unwrap();
+        assert!(log.report().is_empty());
+        let times: Vec<_> = log.events().iter().map(|e| e.time(log.units())).collect();
+        assert_eq!("2020-02-29 23:59:59.900", times[0].to_string());
+        assert_eq!("2020-03-01 00:00:00.100", times[1].to_string());
+        let views: Vec<EventView> = mapped.events().map(Result::unwrap).collect();
+        assert_eq!(times, views.iter().map(|v| v.time()).collect::<Vec<_>>());
+
+        // Leap days in years without them are rejected.
+        let line = text.lines().next().unwrap();
+        let err = Event::parse(line, 2019, &mut UnitTable::new()).unwrap_err();
+        assert_eq!(ParseErrorKind::WrongTimeFormat, err.typ().kind());
+    }
+
+    #[test]
+    fn parse_report() {
+        env_init();
+
+        let log = "garbage\n3/9 19:06:00.997  UNIT_DIED,0x0000000000000000,nil,0x80000000,0xF1300073BE000071,\"Fanged Pit Viper\",0xa28\n3/9 19:06:01.784  SWING_MISSED,0xF13000744E0000BE,\"Spitting Cobra\",0xa48,0x000000000014EABC,\"Draleofdeath\",0x512,DODGY\n3/9 19:06:02.206  swing_bogus,0xF1300074480000BC,\"Unyielding Constrictor\",0xa48,0x000000000014EABC,\"Draleofdeath\",0x512,DODGE";
+
+        let log = Log::from_str(log).expect("Unable to parse log");
+        assert_eq!(1, log.events().len());
+
+        let report = log.report();
+        assert_eq!(
+            vec![1, 3, 4],
+            report
+                .rejected()
+                .iter()
+                .map(|r| r.line())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!("garbage", report.rejected()[0].raw());
+        assert_eq!(7, report.rejected()[1].col());
+        assert_eq!(1, report.count(ParseErrorKind::WrongHeadLength));
+        assert_eq!(1, report.count(ParseErrorKind::InvalidArg));
+        assert_eq!(1, report.count(ParseErrorKind::UnknownEventType));
+        assert_eq!(0, report.count(ParseErrorKind::WrongTimeFormat));
+
+        let err = match Log::read_file("logs/does_not_exist.txt") {
+            Ok(_) => panic!("Read log that does not exist"),
+            Err(err) => err
+        };
+        assert_eq!(ParseErrorKind::IOErr, err.typ().kind());
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn unknown_event() {
+        env_init();
+
+        let log = "3/9 19:06:02.206  SWING_BOGUS,0xF1300074480000BC,\"Unyielding Constrictor\",0xa48,0x000000000014EABC,\"Draleofdeath\",0x512,DODGE\n3/9 19:06:03.000  ZONE_CHANGE,571,\"Gundrak\"";
+
+        let log = Log::from_str(log).expect("Unable to parse log");
+        assert!(log.report().is_empty());
+        assert_eq!(2, log.events().len());
+
+        let bogus = &log.events()[0];
+        assert_eq!(EventType::Unknown("SWING_BOGUS".into()), bogus.typ());
+        assert_eq!("Draleofdeath", bogus.target(log.units()).unwrap().name());
+        assert_eq!(
+            Some("DODGE"),
+            bogus
+                .payload()
+                .raw()
+                .and_then(|raw| raw.last())
+                .map(|s| s.as_str())
+        );
+
+        let zone = &log.events()[1];
+        assert!(zone.source(log.units()).is_none());
+        assert_eq!(
+            &vec!["571".to_string(), "\"Gundrak\"".to_string()],
+            zone.payload().raw().unwrap()
+        );
+    }
+
+    #[test]
+    fn tokenize_fields() {
+        env_init();
+
+        let fields = tokenize(r#"ENCHANT_APPLIED,nil,"Sir \"Comma\", the Brave",0x1a,-2,DODGE"#)
+            .expect("Unable to tokenize line");
+        assert_eq!(6, fields.len());
+        assert_eq!(Value::Word("ENCHANT_APPLIED"), *fields[0].value());
+        assert!(fields[1].is_nil());
+        assert_eq!(None, fields[1].str());
+        assert_eq!(Some(r#"Sir "Comma", the Brave"#), fields[2].str());
+        assert_eq!(r#""Sir \"Comma\", the Brave""#, fields[2].raw());
+        assert_eq!(Some(0x1a), fields[3].hex());
+        assert_eq!(Some(-2), fields[4].int());
+        assert_eq!(Some("DODGE"), fields[5].str());
+
+        assert_eq!(1, tokenize(r#"A,"unclosed, B"#).unwrap_err().col());
+        assert_eq!(1, tokenize(r#"A,"closed"early,B"#).unwrap_err().col());
+
+        let mut units = UnitTable::new();
+        let event = Event::parse(r#"3/9 19:06:01.784  SPELL_DAMAGE,0xF13000744E0000BE,"Cobra, the Spitting",0xa48,0x000000000014EABC,"Draleofdeath",0x512,48130,"Spit, Venom",0x8,380,0,8,0,0,0,nil,nil,nil"#, 2020, &mut units)
+            .expect("Unable to parse event");
+        assert_eq!("Cobra, the Spitting", event.source(&units).unwrap().name());
+        assert_eq!("Draleofdeath", event.target(&units).unwrap().name());
+        assert_eq!("Spit, Venom", event.payload().spell().unwrap().name());
+        assert_eq!(Some(380), event.amount());
+    }
+
+    #[test]
+    fn versioned_log() {
+        env_init();
+
+        let log = r#"9/14 20:31:02.351  COMBAT_LOG_VERSION,20,ADVANCED_LOG_ENABLED,1,BUILD_VERSION,10.0.2,PROJECT_ID,1
+9/14 20:31:03.000  ENCOUNTER_START,2512,"Vigilant Guardian",16,20,2481
+9/14 20:31:05.123  SPELL_DAMAGE,Player-1234-0ABCDEF,"Ikiharu-Realm",0x511,0x0,Creature-0-3019-2450-28297-175732-00001C2F1B,"Training Dummy",0x10a48,0x0,589,"Shadow Word: Pain",0x20,Creature-0-3019-2450-28297-175732-00001C2F1B,0000000000000000,4931810,5000000,0,0,5045,0,1,0,0,0,-2124.65,-4925.06,84,0.0000,70,1290,1064,-1,32,0,0,0,nil,nil,nil,ST
+9/14 20:31:06.000  SWING_MISSED,Creature-0-3019-2450-28297-175732-00001C2F1B,"Training Dummy",0x10a48,0x0,Player-1234-0ABCDEF,"Ikiharu-Realm",0x511,0x0,ABSORB,nil,120
+9/14 20:35:08.000  ENCOUNTER_END,2512,"Vigilant Guardian",16,20,1,245123"#;
+
+        let log = Log::from_str(log).expect("Unable to parse log");
+        assert!(log.report().is_empty());
+        let events = log.events();
+        assert_eq!(5, events.len());
+
+        assert_eq!(
+            Some(LogFormat::Versioned {
+                version:  20,
+                advanced: true
+            }),
+            events[0].payload().format()
+        );
+        assert_eq!(EventType::EncounterStart, events[1].typ());
+        assert!(events[1].source(log.units()).is_none());
+
+        let damage = &events[2];
+        let source = damage.source(log.units()).expect("Source missing");
+        assert_eq!("Player-1234-0ABCDEF", source.guid().to_string());
+        assert_eq!(GuidType::Player, source.guid().typ());
+        assert!(source.is_player());
+        let target = damage.target(log.units()).expect("Target missing");
+        assert_eq!(Some(175732), target.entry());
+        assert_eq!(Some(0x1C2F1B), target.guid().spawn_serial());
+        assert_eq!(Some(1290), damage.amount());
+        assert_eq!(0, damage.payload().damage().unwrap().overkill());
+        assert_eq!(32, damage.payload().damage().unwrap().school());
+
+        let miss = match events[3].payload() {
+            Payload::Combat {
+                suffix: SuffixParams::Missed(miss),
+                ..
+            } => miss,
+            other => panic!("Expected a miss, found {:?}", other)
+        };
+        assert_eq!(Some(120), miss.amount());
+
+        match events[4].payload() {
+            Payload::EncounterEnd(end) => {
+                assert_eq!((true, Some(245_123)), (end.success(), end.fight_time()))
+            }
+            other => panic!("Expected the end of an encounter, found {:?}", other)
+        }
+    }
+
+    #[test]
+    fn stream_events() {
+        env_init();
+
+        let file = File::open("logs/utgarde_keep.txt").expect("Unable to open log");
+        let reader = EventReader::new(BufReader::new(file)).resolved();
+
+        assert_eq!(
+            1_624_123,
+            extract::damage_dealt(
+                &Unit::new(0x13b13c, "Nundo".into()),
+                reader.filter_map(Result::ok)
+            )
+        );
+
+        let mut reader = EventReader::new("\r\ngarbage\r\n".as_bytes());
+        assert!(reader.next().expect("Line has been skipped").is_err());
+        assert_eq!(2, reader.line_number());
+        assert_eq!("garbage", reader.line());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn follow_log() {
+        env_init();
+
+        let lines: Vec<String> = std::fs::read_to_string("logs/dummy_damage.txt")
+            .expect("Unable to read log")
+            .lines()
+            .take(20)
+            .map(|line| format!("{}\n", line))
+            .collect();
+
+        let path = std::env::temp_dir().join(format!("follow_log_{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let options = ParseOptions::new().year(2020);
+        let mut follower = LogFollower::new(&path, &options);
+        let mut events = Vec::new();
+        assert_eq!(0, follower.poll(|e, _| events.push(e)).unwrap());
+
+        let mut file = File::create(&path).expect("Unable to create log");
+        file.write_all(lines[..10].concat().as_bytes()).unwrap();
+        // Only the first half of the next line has been written so far.
+        let (head, tail) = lines[10].split_at(lines[10].len() / 2);
+        file.write_all(head.as_bytes()).unwrap();
+        assert_eq!(10, follower.poll(|e, _| events.push(e)).unwrap());
+
+        file.write_all(tail.as_bytes()).unwrap();
+        file.write_all(lines[11..].concat().as_bytes()).unwrap();
+        assert_eq!(10, follower.poll(|e, _| events.push(e)).unwrap());
+        assert_eq!(20, follower.line_number());
+        assert!(events.iter().all(Result::is_ok));
+
+        // Starting a new log in the same file begins from the first line again.
+        let mut file = File::create(&path).expect("Unable to truncate log");
+        file.write_all(lines[..3].concat().as_bytes()).unwrap();
+        assert_eq!(3, follower.poll(|e, _| events.push(e)).unwrap());
+        assert_eq!(3, follower.line_number());
+        assert_eq!(
+            events[0].as_ref().unwrap().time(follower.units()),
+            events[20].as_ref().unwrap().time(follower.units())
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compressed_logs() {
+        env_init();
+
+        let options = ParseOptions::new().year(2020);
+        let plain = Log::read_file_with("logs/dummy_damage.txt", options.clone())
+            .expect("Unable to read log");
+        assert!(!plain.events().is_empty());
+
+        let paths = [
+            ("logs/dummy_damage.txt.gz", cfg!(feature = "gzip")),
+            ("logs/dummy_damage.txt.zst", cfg!(feature = "zstd"))
+        ];
+        for (path, supported) in &paths {
+            let log = match Log::read_file_with(path, options.clone()) {
+                Ok(log) => log,
+                Err(err) => {
+                    // Compressions without their feature are detected, but
+                    // cannot be read.
+                    assert!(!supported, "Unable to read compressed log: {}", err);
+                    match err.typ() {
+                        ParseErrorType::IOErr(err) => {
+                            assert_eq!(std::io::ErrorKind::Unsupported, err.kind())
+                        }
+                        other => panic!("Expected an unsupported format, found {:?}", other)
+                    }
+                    continue;
+                }
+            };
+            assert!(supported);
+            assert!(log.report().is_empty());
+            assert_eq!(plain.events().len(), log.events().len());
+            assert_eq!(
+                plain.events().last().unwrap().time(plain.units()),
+                log.events().last().unwrap().time(log.units())
+            );
+        }
+
+        assert_eq!(Compression::Gzip, Compression::detect(&[0x1f, 0x8b, 0x08]));
+        assert_eq!(Compression::None, Compression::detect(b"4/22 14:44"));
+    }
+
+    #[test]
+    fn binary_cache() {
+        env_init();
+
+        let dir = std::env::temp_dir().join(format!("binary_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let log = Log::read_file("logs/turm_utgarde.txt").expect("Unable to read log");
+        let path = dir.join("turm_utgarde.cache");
+        log.save_cache(&path).expect("Unable to write cache");
+        let cached = Log::load_cache(&path).expect("Unable to load cache");
+        assert_eq!(log.events().len(), cached.events().len());
+        for (event, cached) in log.events().iter().zip(cached.events()) {
+            assert_eq!(format!("{:?}", event), format!("{:?}", cached));
+        }
+
+        // Caches of other versions are rejected.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[4] = bytes[4].wrapping_add(1);
+        std::fs::write(&path, bytes).unwrap();
+        assert!(Log::load_cache(&path).is_err());
+
+        // Truncated and garbled caches are rejected instead of being read as
+        // far as possible.
+        let bytes = cache::encode(log.events(), log.units(), log.report());
+        for len in [0, 3, 8, bytes.len() / 2, bytes.len() - 1] {
+            assert!(cache::decode(&bytes[..len]).is_err());
+        }
+        let mut garbage = bytes[..8].to_vec();
+        garbage.extend((0..4096u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8));
+        assert!(cache::decode(&garbage).is_err());
+
+        // Differences that overflow the line of the event before.
+        let mut overflow = bytes[..8].to_vec();
+        overflow.extend([0, 0, 0, 2]);
+        overflow.extend([
+            0, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01
+        ]);
+        overflow.extend([0, 4, 0, 0, 0, 0]);
+        overflow.extend([0, 2, 0, 4, 0, 0, 0, 0]);
+        overflow.push(0);
+        assert!(cache::decode(&overflow).is_err());
+        overflow[13] = 0xfc;
+        assert_eq!(2, cache::decode(&overflow).unwrap().0.len());
+
+        // Reading errors in a report are kept with their message.
+        let mut report = ParseReport::new();
+        let err = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8"
+        );
+        report.reject(3, "garbage", err.into());
+        let bytes = cache::encode(&[], &UnitTable::new(), &report);
+        let (_, _, cached) = cache::decode(&bytes).expect("Unable to decode cache");
+        assert_eq!(1, cached.count(ParseErrorKind::IOErr));
+        assert_eq!(3, cached.rejected()[0].line());
+        assert_eq!(
+            "unable to read log: stream did not contain valid UTF-8",
+            cached.rejected()[0].error().to_string()
+        );
+
+        // A cache newer than the log is used instead of parsing it again, so
+        // a foreign cache shows up in its place.
+        let source = dir.join("dummy_damage.txt");
+        std::fs::copy("logs/dummy_damage.txt", &source).unwrap();
+        let parsed = Log::read_file_cached(&source).expect("Unable to read log");
+        assert!(Log::cache_path(&source).exists());
+        let foreign = Log::from_str(
+            "3/9 19:06:00.997  UNIT_DIED,0x0000000000000000,nil,0x80000000,0xF1300073BE000071,\"Fanged Pit Viper\",0xa28"
+        )
+        .unwrap();
+        foreign.save_cache(Log::cache_path(&source)).unwrap();
+        assert_eq!(1, Log::read_file_cached(&source).unwrap().events().len());
+        assert!(parsed.events().len() > 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_json_shape() {
+        env_init();
+
+        let mut units = UnitTable::new();
+        let event = Event::parse("3/9 19:06:01.784  SWING_DAMAGE,0xF13000744E0000BE,\"Spitting Cobra\",0xa48,0x000000000014EABC,\"Draleofdeath\",0x512,380,0,1,0,0,0,nil,nil,nil", 2020, &mut units).unwrap();
+        let json = serde_json::to_value(&event).expect("Unable to serialize event");
+        assert_eq!("SWING_DAMAGE", json["type"]);
+        assert_eq!(0, json["source"]);
+        assert_eq!(1, json["target"]);
+
+        let table = serde_json::to_value(&units).expect("Unable to serialize units");
+        assert_eq!("2020-03-09T00:00:00", table["start"]);
+        assert_eq!("0xF13000744E0000BE", table["units"][0]["guid"]);
+        assert_eq!("Spitting Cobra", table["units"][0]["name"]);
+        assert_eq!(0xa48, table["units"][0]["flags"]);
+        let back: UnitTable = serde_json::from_value(table).unwrap();
+        assert_eq!(Some(units.units()[1].clone()), event.target(&back).cloned());
+        assert_eq!(event.time(&units), event.time(&back));
+        assert_eq!(((19 * 60 + 6) * 60 + 1) * 1000 + 784, json["time"]);
+        assert_eq!("none", json["payload"]["combat"]["prefix"]);
+        assert_eq!(380, json["payload"]["combat"]["suffix"]["damage"]["amount"]);
+
+        let back: Event = serde_json::from_value(json).expect("Unable to deserialize event");
+        assert_eq!(format!("{:?}", event), format!("{:?}", back));
+
+        let log = Log::read_file("logs/dummy_damage.txt").expect("Unable to read log");
+        let json = serde_json::to_string(log.events()).unwrap();
+        let events: Vec<Event> = serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{:?}", log.events()), format!("{:?}", events));
+
+        let encounters = Encounter::all_encounters(log.events().clone(), log.units());
+        let json = serde_json::to_value(&encounters).unwrap();
+        assert!(json[0]["events"].is_array());
+        assert!(json[0]["involved"].is_array());
+        assert!(json[0]["units"]["units"].is_array());
+        let back: Vec<Encounter> = serde_json::from_value(json).unwrap();
+        assert_eq!(encounters.len(), back.len());
+        for (encounter, back) in encounters.iter().zip(&back) {
+            assert!(encounter.units().len() <= log.units().len());
+            for unit in encounter.involved() {
+                assert!(back.units().find(unit).is_some());
+            }
+            for (event, back_event) in encounter.events().iter().zip(back.events()) {
+                assert_eq!(
+                    event.display(encounter.units()).to_string(),
+                    back_event.display(back.units()).to_string()
+                );
+            }
+        }
+
+        let err = ParseError::new(ParseErrorType::UnknownEventType("BOGUS".into()), 2);
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(
+            serde_json::json!({"kind": "UnknownEventType", "col": 2, "detail": "BOGUS"}),
+            json
+        );
+        let back: ParseError = serde_json::from_value(json).unwrap();
+        assert_eq!(err.to_string(), back.to_string());
+    }
+
+    #[test]
+    fn write_round_trip() {
+        env_init();
+
+        let mut paths: Vec<_> = std::fs::read_dir("logs")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+
+        for path in paths {
+            let log = Log::read_file(&path).expect("Unable to read log");
+            let original = std::fs::read_to_string(&path).unwrap();
+            let rejected: HashSet<usize> =
+                log.report().rejected().iter().map(|r| r.line()).collect();
+            let lines = original
+                .lines()
+                .enumerate()
+                .filter(|(i, _)| !rejected.contains(&(i + 1)))
+                .map(|(_, line)| line);
+
+            let mut count = 0;
+            for (line, event) in lines.zip(log.events()) {
+                assert_eq!(
+                    line,
+                    event.display(log.units()).to_string(),
+                    "in {}",
+                    path.display()
+                );
+                count += 1;
+            }
+            assert_eq!(log.events().len(), count);
+
+            let mut written = Vec::new();
+            log.write_to(&mut written).unwrap();
+            let written = String::from_utf8(written).unwrap();
+            assert_eq!(log.to_string(), written);
+
+            // The year is not written, so it has to be given again.
+            let year = log.events()[0].time(log.units()).year();
+            let reparsed_log = Log::parse_with(&written, &ParseOptions::new().year(year)).unwrap();
+            assert!(reparsed_log.report().is_empty());
+            assert_eq!(log.events().len(), reparsed_log.events().len());
+            // The positions differ for logs that have not been written with
+            // the line endings of the client.
+            for (event, reparsed) in log.events().iter().zip(reparsed_log.events()) {
+                assert_eq!(event.time(log.units()), reparsed.time(reparsed_log.units()));
+                assert_eq!(event.typ(), reparsed.typ());
+                assert_eq!(
+                    format!(
+                        "{:?}",
+                        (event.source(log.units()), event.target(log.units()))
+                    ),
+                    format!(
+                        "{:?}",
+                        (
+                            reparsed.source(reparsed_log.units()),
+                            reparsed.target(reparsed_log.units())
+                        )
+                    )
+                );
+                assert_eq!(event.payload(), reparsed.payload());
+                assert_eq!(event.line(), reparsed.line());
+            }
+        }
+    }
+
+    #[test]
+    fn write_versioned() {
+        env_init();
+
+        let lines = [
+            "9/14 20:31:02.351  COMBAT_LOG_VERSION,20,ADVANCED_LOG_ENABLED,1,BUILD_VERSION,10.0.2,PROJECT_ID,1",
+            r#"9/14 20:31:05.123  SPELL_DAMAGE,Player-1234-0ABCDEF,"Ikiharu-Realm",0x511,0x0,Creature-0-3019-2450-28297-175732-00001C2F1B,"Training Dummy",0x10a48,0x0,589,"Shadow Word: Pain",0x20,Creature-0-3019-2450-28297-175732-00001C2F1B,0000000000000000,4931810,5000000,0,0,5045,0,1,0,0,0,-2124.65,-4925.06,84,0.0000,70,1290,1064,-1,32,0,0,0,nil,nil,nil,ST"#,
+            r#"9/14 20:31:05.500  SPELL_HEAL,Player-1234-0ABCDEF,"Ikiharu-Realm",0x511,0x0,Player-1234-0ABCDEF,"Ikiharu-Realm",0x511,0x0,2061,"Flash Heal",0x2,Player-1234-0ABCDEF,0000000000000000,100,100,0,0,5045,0,1,0,0,0,-2124.65,-4925.06,84,0.0000,70,800,760,120,0,1"#,
+            r#"9/14 20:31:05.800  SPELL_ENERGIZE,Player-1234-0ABCDEF,"Ikiharu-Realm",0x511,0x0,Player-1234-0ABCDEF,"Ikiharu-Realm",0x511,0x0,34914,"Vampiric Touch",0x20,Player-1234-0ABCDEF,0000000000000000,100,100,0,0,5045,0,1,0,0,0,-2124.65,-4925.06,84,0.0000,70,3,1,13,100"#,
+            r#"9/14 20:31:06.000  SWING_MISSED,Creature-0-3019-2450-28297-175732-00001C2F1B,"Training Dummy",0x10a48,0x0,Player-1234-0ABCDEF,"Ikiharu-Realm",0x511,0x0,ABSORB,1,120"#,
+            r#"9/14 20:31:06.500  ENVIRONMENTAL_DAMAGE,0000000000000000,nil,0x80000000,0x80000000,Player-1234-0ABCDEF,"Ikiharu-Realm",0x511,0x0,Player-1234-0ABCDEF,0000000000000000,90,100,0,0,5045,0,1,0,0,0,-2124.65,-4925.06,84,0.0000,70,FALLING,10,10,-1,1,0,0,0,nil,nil,nil"#,
+            r#"9/14 20:31:07.000  SWING_DAMAGE,Player-1234-0ABCDEF,"Ikiharu-Realm",0x511,0x0,Creature-0-3019-2450-28297-175732-00001C2F1B,"Training Dummy",0x10a48,0x8,Player-1234-0ABCDEF,0000000000000000,100,100,0,0,5045,0,1,0,0,0,-2124.65,-4925.06,84,0.0000,70,500,520,0,1,0,0,0,nil,nil,nil"#,
+            r#"9/14 20:31:07.000  UNIT_DIED,0000000000000000,nil,0x80000000,0x80000000,Creature-0-3019-2450-28297-175732-00001C2F1B,"Training Dummy",0x10a48,0x8,0"#
+        ];
+        let text: String = lines.iter().map(|line| format!("{}\r\n", line)).collect();
+
+        // The columns the events do not keep are written back as they are, so
+        // the log is the very same.
+        let log = Log::from_str(&text).expect("Unable to parse log");
+        assert!(log.report().is_empty());
+        let written = log.to_string();
+        assert_eq!(text, written);
+        let bytes = cache::encode(log.events(), log.units(), log.report());
+        let (events, units, _) = cache::decode(&bytes).expect("Unable to decode cache");
+        assert_eq!(text, Log::from_events(events, units).to_string());
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(log.units()).unwrap();
+            let units: UnitTable = serde_json::from_str(&json).unwrap();
+            let json = serde_json::to_string(log.events()).unwrap();
+            let events: Vec<Event> = serde_json::from_str(&json).unwrap();
+            assert_eq!(text, Log::from_events(events, units).to_string());
+        }
+
+        // They are anonymized like the rest of the line.
+        let anonymized = log.anonymized(42).to_string();
+        assert!(!anonymized.contains("0ABCDEF"));
+        assert_eq!(
+            anonymized.matches("\"Player1\"").count(),
+            text.matches("\"Ikiharu-Realm\"").count()
+        );
+
+        // Events that have not been read from a log have their raid flags
+        // written as zeros.
+        let mut units = UnitTable::new();
+        let event = Event::parse_with(
+            lines[1],
+            2022,
+            log.events()[0].payload().format().unwrap(),
+            &mut units
+        )
+        .unwrap();
+        let event = Event::new(
+            event.time(&units),
+            event.typ(),
+            event.source_ref(),
+            event.target_ref(),
+            event.payload().clone(),
+            &mut units
+        )
+        .unwrap();
+        let line = event
+            .display_with(
+                &units,
+                LogFormat::Versioned {
+                    version:  20,
+                    advanced: true
+                }
+            )
+            .to_string();
+        assert!(line.contains(",\"Ikiharu-Realm\",0x511,0x0,"));
+        assert!(line.ends_with(",1290,1290,-1,32,0,0,0,nil,nil,nil"));
+
+        let reparsed = Log::from_str(&written).expect("Unable to parse written log");
+        assert!(reparsed.report().is_empty());
+        assert_eq!(log.events().len(), reparsed.events().len());
+        for (event, reparsed_event) in log.events().iter().zip(reparsed.events()) {
+            assert_eq!(
+                event.time(log.units()),
+                reparsed_event.time(reparsed.units())
+            );
+            assert_eq!(event.typ(), reparsed_event.typ());
+            assert_eq!(
+                event.source(log.units()),
+                reparsed_event.source(reparsed.units())
+            );
+            assert_eq!(
+                event.target(log.units()),
+                reparsed_event.target(reparsed.units())
+            );
+            assert_eq!(event.payload(), reparsed_event.payload());
+        }
+        assert_eq!(written, reparsed.to_string());
+    }
+
+    #[test]
+    fn merge_logs() {
+        env_init();
+
+        let log = Log::read_file("logs/turm_utgarde.txt").expect("Unable to read log");
+        let events = log.events();
+        let (first, second) = (events.len() * 2 / 3, events.len() / 3);
+
+        // The second client only saw the later part of the fight and its clock
+        // is 90 seconds ahead.
+        let skew = chrono::Duration::seconds(90);
+        let own = Log::from_events(events[..first].to_vec(), log.units().clone());
+        let mut units = log.units().clone();
+        let skewed = events[second..]
+            .iter()
+            .map(|e| {
+                Event::new(
+                    e.time(log.units()) + skew,
+                    e.typ(),
+                    e.source_ref(),
+                    e.target_ref(),
+                    e.payload().clone(),
+                    &mut units
+                )
+                .unwrap()
+            })
+            .collect();
+        let other = Log::from_events(skewed, units);
+
+        let merged = own.merge(&other);
+        assert_eq!(Some(skew), merged.skew());
+        assert_eq!(first - second, merged.duplicates());
+        assert_eq!(events.len(), merged.log().events().len());
+        assert!(merged
+            .log()
+            .events()
+            .windows(2)
+            .all(|w| w[0].time(merged.log().units()) <= w[1].time(merged.log().units())));
+        assert_eq!(
+            events.last().unwrap().time(log.units()),
+            merged
+                .log()
+                .events()
+                .last()
+                .unwrap()
+                .time(merged.log().units())
+        );
+
+        // Logs without shared events are merged as they are.
+        let unrelated = Log::from_events(Vec::new(), UnitTable::new()).merge(&own);
+        assert_eq!(None, unrelated.skew());
+        assert_eq!(first, unrelated.log().events().len());
+
+        // The same player is the player of one log and in the party of the
+        // other, so the flags of the units differ.
+        let text = std::fs::read_to_string("logs/dummy_damage.txt").unwrap();
+        let own = Log::parse_with(&text, &ParseOptions::new().year(2020)).unwrap();
+        let party = Log::parse_with(
+            &text.replace(",0x511,", ",0x512,"),
+            &ParseOptions::new().year(2020)
+        )
+        .unwrap();
+        assert_ne!(
+            own.units().units()[0].flags().bits(),
+            party.units().units()[0].flags().bits()
+        );
+        let mut units = party.units().clone();
+        let skewed = party
+            .events()
+            .iter()
+            .map(|e| {
+                Event::new(
+                    e.time(party.units()) + skew,
+                    e.typ(),
+                    e.source_ref(),
+                    e.target_ref(),
+                    e.payload().clone(),
+                    &mut units
+                )
+                .unwrap()
+            })
+            .collect();
+        let other = Log::from_events(skewed, units);
+        let merged = own.merge(&other);
+        assert_eq!(Some(skew), merged.skew());
+        assert_eq!(own.events().len(), merged.duplicates());
+        assert_eq!(own.events().len(), merged.log().events().len());
+    }
+
+    #[test]
+    fn split_sessions() {
+        env_init();
+
+        // Two evenings appended to the same file.
+        let mut text = std::fs::read_to_string("logs/turm_utgarde.txt").unwrap();
+        text.push_str(&std::fs::read_to_string("logs/dummy_damage.txt").unwrap());
+        let log = Log::parse_with(&text, &ParseOptions::new().year(2020)).unwrap();
+
+        let sessions = log.sessions(chrono::Duration::hours(1));
+        assert_eq!(2, sessions.len());
+        assert_eq!(
+            log.events().len(),
+            sessions
+                .iter()
+                .map(|s| s.log().events().len())
+                .sum::<usize>()
+        );
+        assert_eq!(14, sessions[0].start().day());
+        assert_eq!(22, sessions[1].start().day());
+        assert!(sessions[0].start() <= sessions[0].end());
+        assert!(sessions[0].players().iter().any(|p| p.name() == "Milune"));
+        assert!(sessions[1].players().iter().any(|p| p.name() == "Ikiharu"));
+        assert!(!sessions[1].players().iter().any(|p| p.name() == "Milune"));
+        assert!(sessions
+            .iter()
+            .all(|s| s.players().iter().all(Unit::is_player)));
+
+        // Each session only keeps the units of its own events.
+        let (first, second) = (sessions[0].log(), sessions[1].log());
+        assert_eq!(log.events()[0].time(log.units()), sessions[0].start());
+        assert!(second.units().len() < log.units().len());
+        assert!(!second
+            .units()
+            .iter()
+            .any(|(_, unit)| unit.name() == "Milune"));
+        for (event, original) in first.events().iter().zip(log.events()) {
+            assert_eq!(
+                original.display(log.units()).to_string(),
+                event.display(first.units()).to_string()
+            );
+        }
+        let last = second.events().last().unwrap();
+        assert_eq!(
+            log.raw_line(log.events().last().unwrap()),
+            second.raw_line(last)
+        );
+
+        // The dummy has not been attacked for four seconds once.
+        let gap = chrono::Duration::seconds(3);
+        let sessions = log.sessions(gap);
+        assert!(sessions.len() > 2);
+        assert!(sessions.windows(2).all(|w| w[1].start() - w[0].end() > gap));
+    }
+
+    #[test]
+    fn anonymize_players() {
+        env_init();
+
+        let log = Log::read_file("logs/turm_utgarde.txt").expect("Unable to read log");
+        let anonymized = log.anonymized(42);
+        assert_eq!(log.events().len(), anonymized.events().len());
+
+        let mut written = Vec::new();
+        anonymized.write_to(&mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(!written.contains("\"Milune\""));
+        assert!(!written.contains("0x00000000000E8806"));
+        assert!(written.contains("\"Dark Ranger Marrah\""));
+        assert!(written.contains("\"Player1\""));
+
+        // Every player is mapped to the same pseudonym throughout the log.
+        let mut pseudonyms = HashMap::new();
+        for (event, pseudo_event) in log.events().iter().zip(anonymized.events()) {
+            let units = event
+                .source(log.units())
+                .into_iter()
+                .chain(event.target(log.units()));
+            let pseudos = pseudo_event
+                .source(anonymized.units())
+                .into_iter()
+                .chain(pseudo_event.target(anonymized.units()));
+            for (unit, pseudo) in units.zip(pseudos) {
+                if unit.guid().typ() == GuidType::Player {
+                    assert_eq!(GuidType::Player, pseudo.guid().typ());
+                    assert_eq!(unit.flags(), pseudo.flags());
+                    assert_eq!(pseudo, *pseudonyms.entry(unit).or_insert(pseudo));
+                }
+                else {
+                    assert_eq!(unit, pseudo);
+                }
+            }
+        }
+        let distinct: HashSet<_> = pseudonyms.values().collect();
+        assert_eq!(pseudonyms.len(), distinct.len());
+
+        // The seed determines the pseudonyms.
+        let again = log.anonymized(42);
+        assert_eq!(anonymized.to_string(), again.to_string());
+        assert_ne!(anonymized.to_string(), log.anonymized(7).to_string());
+    }
+
+    #[test]
+    fn event_positions() {
+        env_init();
+
+        let text = std::fs::read_to_string("logs/dummy_damage.txt").unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let starts: Vec<usize> = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        let mut logs = vec![
+            Log::from_str(&text).unwrap(),
+            Log::read_file("logs/dummy_damage.txt").unwrap(),
+        ];
+        if cfg!(feature = "zstd") {
+            logs.push(Log::read_file("logs/dummy_damage.txt.zst").unwrap());
+        }
+        for log in &logs {
+            assert_eq!(lines.len(), log.events().len());
+            for (i, event) in log.events().iter().enumerate().step_by(97) {
+                assert_eq!(Some(i + 1), event.line());
+                assert_eq!(Some(starts[i] as u64), event.offset());
+                assert_eq!(Some(lines[i].to_string()), log.raw_line(event));
+                assert_eq!(Some(lines[i]), log.raw_line_in(event, &text));
+            }
+        }
+
+        // Line numbers count the lines that have been rejected as well.
+        let text = concat!(
+            "4/22 14:44:32.944  SPELL_CAST_SUCCESS,0x000000000015A6D4,\"Ikiharu\",0x511,0x0000000000000000,nil,0x80000000,33736,\"Water Shield\",0x8\r\n",
+            "garbage\r\n",
+            "\r\n",
+            "4/22 14:44:35.063  UNIT_DIED,0x0000000000000000,nil,0x80000000,0x000000000015A6D4,\"Ikiharu\",0x511\r\n"
+        );
+        let log = Log::from_str(text).unwrap();
+        let died = &log.events()[1];
+        assert_eq!(Some(4), died.line());
+        assert_eq!(
+            Some("4/22 14:44:35.063  UNIT_DIED,0x0000000000000000,nil,0x80000000,0x000000000015A6D4,\"Ikiharu\",0x511"),
+            log.raw_line(died).as_deref()
+        );
+        assert_eq!(log.raw_line(died).as_deref(), log.raw_line_in(died, text));
+
+        // Events that have not been read from the log have no line in it.
+        let copy = Event::new(
+            died.time(log.units()),
+            died.typ(),
+            died.source_ref(),
+            died.target_ref(),
+            died.payload().clone(),
+            &mut log.units().clone()
+        )
+        .unwrap();
+        assert_eq!(None, log.raw_line(&copy));
+        assert_eq!(None, log.raw_line_in(&copy, text));
+    }
+
+    #[test]
+    fn unit_table() {
+        env_init();
+
+        assert_eq!(4, std::mem::size_of::<Option<UnitRef>>());
+
+        let log = Log::read_file("logs/turm_utgarde.txt").expect("Unable to read log");
+        let units = log.units();
+        assert!(units.len() < log.events().len());
+
+        // Every unit is only stored once for every set of flags it appears
+        // with.
+        let distinct: HashSet<_> = units
+            .units()
+            .iter()
+            .map(|u| (u.guid(), u.name().clone(), u.flags().bits()))
+            .collect();
+        assert_eq!(units.len(), distinct.len());
+
+        let milune = log
+            .events()
+            .iter()
+            .filter_map(Event::source_ref)
+            .find(|&unit| units[unit].name() == "Milune")
+            .expect("Milune is missing");
+        assert_eq!(Some(milune), units.find(&units[milune]));
+        assert!(log
+            .events()
+            .iter()
+            .filter(|e| e
+                .source(units)
+                .is_some_and(|u| u.name() == "Milune" && u.flags() == units[milune].flags()))
+            .all(|e| e.source_ref() == Some(milune)));
+
+        let mut table = units.clone();
+        assert_eq!(milune, table.intern(units[milune].clone()));
+        assert_eq!(units.len(), table.len());
+        let stranger = table.intern(Unit::new(0x1234, "Stranger".into()));
+        assert_eq!(units.len(), stranger.index());
+        assert_eq!(None, units.get(stranger));
+    }
+
+    #[test]
+    fn long_logs() {
+        env_init();
+
+        // Logs may span more than the 49 days that fit into 32 bits of
+        // milliseconds.
+        let log = Log::from_str(concat!(
+            "3/1 20:00:00.000  UNIT_DIED,0x0000000000000000,nil,0x80000000,0x000000000014EABC,\"Draleofdeath\",0x512\n",
+            "5/1 20:00:00.000  UNIT_DIED,0x0000000000000000,nil,0x80000000,0x000000000014EABC,\"Draleofdeath\",0x512\n"
+        ))
+        .unwrap();
+        assert_eq!(2, log.events().len());
+        let days: Vec<_> = log
+            .events()
+            .iter()
+            .map(|e| e.time(log.units()).ordinal())
+            .collect();
+        assert_eq!(61, days[1] - days[0]);
+
+        // When the clock is set back, events may be before the start of the
+        // day of the first one.
+        let log = Log::from_str(concat!(
+            "3/2 00:00:05.000  UNIT_DIED,0x0000000000000000,nil,0x80000000,0x000000000014EABC,\"Draleofdeath\",0x512\n",
+            "3/1 23:59:59.000  UNIT_DIED,0x0000000000000000,nil,0x80000000,0x000000000014EABC,\"Draleofdeath\",0x512\n"
+        ))
+        .unwrap();
+        assert_eq!(2, log.events().len());
+        let times: Vec<_> = log.events().iter().map(|e| e.time(log.units())).collect();
+        assert_eq!(chrono::Duration::seconds(-6), times[1] - times[0]);
+        assert!(times[1] < log.units().start().unwrap());
+    }
+
+    #[test]
+    fn compact_events() {
+        env_init();
+
+        // The times are stored as offsets from the start of the log, the
+        // amounts in 32 bits, spells and rare payloads behind a pointer and
+        // the names of unknown event types in their payload, which makes
+        // events smaller than the 96 bytes they used to take.
+        assert!(std::mem::size_of::<Payload>() <= 40);
+        assert!(std::mem::size_of::<Event>() <= 72);
+
+        // Amounts that do not fit are rejected instead of being cut off.
+        let mut units = UnitTable::new();
+        let line = "3/9 19:06:01.784  SWING_DAMAGE,0xF13000744E0000BE,\"Spitting Cobra\",0xa48,0x000000000014EABC,\"Draleofdeath\",0x512,5000000000,0,1,0,0,0,nil,nil,nil";
+        let err = Event::parse(line, 2020, &mut units).unwrap_err();
+        assert_eq!(ParseErrorKind::InvalidArg, err.typ().kind());
+        assert_eq!(7, err.col());
+        let event =
+            Event::parse(&line.replace("5000000000", "4294967295"), 2020, &mut units).unwrap();
+        assert_eq!(Some(u64::from(u32::MAX)), event.amount());
+        assert_eq!(
+            chrono::NaiveDate::from_ymd_opt(2020, 3, 9)
+                .unwrap()
+                .and_hms_milli_opt(19, 6, 1, 784),
+            Some(event.time(&units))
+        );
+        // Events may be any time after the start of the log, or before it.
+        let late = "5/1 19:06:01.784  UNIT_DIED,0x0000000000000000,nil,0x80000000,0x000000000014EABC,\"Draleofdeath\",0x512";
+        let early = "2/1 23:59:59.000  UNIT_DIED,0x0000000000000000,nil,0x80000000,0x000000000014EABC,\"Draleofdeath\",0x512";
+        let expected = [((5, 1), (19, 6, 1, 784)), ((2, 1), (23, 59, 59, 0))];
+        for (line, ((month, day), (h, m, s, ms))) in [late, early].iter().zip(&expected) {
+            let event = Event::parse(line, 2020, &mut units).unwrap();
+            assert_eq!(
+                chrono::NaiveDate::from_ymd_opt(2020, *month, *day)
+                    .unwrap()
+                    .and_hms_milli_opt(*h, *m, *s, *ms),
+                Some(event.time(&units))
+            );
+        }
+
+        // Every spell name is only stored once, no matter how many events refer
+        // to it.
+        let log = Log::read_file("logs/utgarde_keep.txt").expect("Unable to read log");
+        let path = std::env::temp_dir().join(format!("compact_events_{}", std::process::id()));
+        log.save_cache(&path).expect("Unable to write cache");
+        let cached = Log::load_cache(&path).expect("Unable to load cache");
+        std::fs::remove_file(&path).unwrap();
+        for log in &[&log, &cached] {
+            let mut names: HashMap<&str, *const u8> = HashMap::new();
+            for spell in log.events().iter().filter_map(|e| e.payload().spell()) {
+                let name = spell.name();
+                assert_eq!(
+                    *names.entry(name).or_insert(name.as_ptr()),
+                    name.as_ptr(),
+                    "{} is stored twice",
+                    name
+                );
+            }
+            assert!(names.len() > 100);
+        }
+
+        let event = log
+            .events()
+            .iter()
+            .find(|e| e.typ() == EventType::Combat(Prefix::Spell, Suffix::Damage))
+            .unwrap();
+        let damage = event.payload().damage().unwrap();
+        assert_eq!(Some(damage.amount()), event.amount());
+        assert!(event.source(log.units()).is_some());
+        assert!(log.events()[0].time(log.units()) <= event.time(log.units()));
+    }
+
+    #[test]
+    fn mapped_log() {
+        env_init();
+
+        let log = Log::read_file("logs/utgarde_keep.txt").expect("Unable to read log");
+        // The log is not written to while the test runs.
+        let mapped =
+            unsafe { MappedLog::open("logs/utgarde_keep.txt") }.expect("Unable to map log");
+
+        let mut units = UnitTable::new();
+        let mut spells = 0;
+        let mut views = 0;
+        for (view, event) in mapped.events().zip(log.events()) {
+            let view = view.expect("Unable to read event");
+            assert_eq!(view.time(), event.time(log.units()));
+            assert_eq!(view.typ(), event.typ());
+            assert_eq!(Some(view.line()), event.line());
+            assert_eq!(
+                view.source().map(UnitView::name),
+                event.source(log.units()).map(|unit| unit.name().as_str())
+            );
+            assert_eq!(
+                view.target().map(UnitView::to_unit).as_ref(),
+                event.target(log.units())
+            );
+            assert_eq!(view.amount(), event.amount());
+
+            // The names point into the mapped file instead of being copied.
+            if let Some(spell) = view.spell() {
+                let raw = view.raw().as_bytes().as_ptr_range();
+                assert!(raw.contains(&spell.name().as_ptr()));
+                assert_eq!(spell.name(), event.payload().spell().unwrap().name());
+                spells += 1;
+            }
+
+            let owned = view.to_event(&mut units).expect("Unable to parse event");
+            assert_eq!(format!("{:?}", owned), format!("{:?}", event));
+            views += 1;
+        }
+        assert_eq!(views, log.events().len());
+        assert!(spells > 0);
+
+        let owned = mapped.to_log().expect("Unable to parse log");
+        assert_eq!(
+            format!("{:?}", owned.events()),
+            format!("{:?}", log.events())
+        );
+        assert_eq!(owned.units().len(), log.units().len());
+
+        // Invalid characters are replaced like when reading the log.
+        let mut text = concat!(
+            "4/22 14:44:32.944  SPELL_CAST_SUCCESS,0x000000000015A6D4,\"Iki?haru\",0x511,0x0000000000000000,nil,0x80000000,33736,\"Water Shield\",0x8\r\n",
+            "4/22 14:44:35.063  UNIT_DIED,0x0000000000000000,nil,0x80000000,0x000000000015A6D4,\"Ikiharu\",0x511\r\n"
+        )
+        .as_bytes()
+        .to_vec();
+        let invalid = text.iter().position(|&b| b == b'?').unwrap();
+        text[invalid] = 0xff;
+        let path = std::env::temp_dir().join(format!("mapped_lossy_{}", std::process::id()));
+        std::fs::write(&path, &text).unwrap();
+        let options = ParseOptions::new().year(2020);
+        let mapped = unsafe { MappedLog::open_with(&path, options.clone()) }.unwrap();
+        let (read, _, _) = parser::parse_reader(&text[..], &options).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let views: Vec<EventView> = mapped.events().map(Result::unwrap).collect();
+        assert_eq!("Iki\u{fffd}haru", views[0].source().unwrap().name());
+        assert_eq!(Some(views[1].offset()), read[1].offset());
+        let (events, report) = mapped.events().to_events(&mut UnitTable::new());
+        assert!(report.is_empty());
+        assert_eq!(format!("{:?}", events), format!("{:?}", read));
+    }
+
+    #[test]
+    fn parallel_parse() {
+        env_init();
+
+        // A log spanning New Year's Eve, switching its format and containing
+        // broken lines, so that chunks have to be parsed again.
+        let dummy = std::fs::read_to_string("logs/dummy_damage.txt").unwrap();
+        let mut lines = Vec::new();
+        for (i, line) in dummy.lines().enumerate() {
+            let (_, rest) = line.split_once(' ').unwrap();
+            let date = if i < 137 { "12/31" } else { "1/1" };
+            lines.push(format!("{} {}", date, rest));
+            if i % 37 == 0 {
+                lines.push("garbage".to_string());
+            }
+            if i == 200 {
+                lines.push(String::new());
+                lines.push(
+                    "1/1 00:00:00.000  COMBAT_LOG_VERSION,9,ADVANCED_LOG_ENABLED,1,\
+                     BUILD_VERSION,3.4.0,PROJECT_ID,11"
+                        .to_string()
+                );
+            }
+        }
+        let text = lines.join("\r\n");
+
+        let options = ParseOptions::new().year(2019);
+        let (events, units, report) = parser::parse_with(&text, &options).unwrap();
+        for size in &[1, 100, 4096, text.len()] {
+            let (parallel_events, parallel_units, parallel_report) =
+                parallel::parse_chunks(text.as_bytes(), &options, *size).unwrap();
+            assert_eq!(format!("{:?}", parallel_events), format!("{:?}", events));
+            assert_eq!(parallel_units.units(), units.units());
+            assert_eq!(
+                format!("{:?}", parallel_report.rejected()),
+                format!("{:?}", report.rejected())
+            );
+            assert_eq!(parallel_report.counts(), report.counts());
+        }
+        assert!(events.iter().any(|e| e.time(&units).year() == 2020));
+        assert!(report.rejected().len() > 5);
+        for rejected in report.rejected() {
+            assert_eq!(rejected.raw(), &lines[rejected.line() - 1]);
+        }
+
+        let log = Log::read_file("logs/utgarde_keep.txt").expect("Unable to read log");
+        let parallel_log =
+            Log::read_file_with("logs/utgarde_keep.txt", ParseOptions::new().parallel(true))
+                .expect("Unable to read log");
+        assert_eq!(
+            format!("{:?}", parallel_log.events()),
+            format!("{:?}", log.events())
+        );
+        let (events, _, _) = parallel::parse_chunks(
+            &std::fs::read("logs/utgarde_keep.txt").unwrap(),
+            &options,
+            64 * 1024
+        )
+        .unwrap();
+        assert_eq!(events.len(), log.events().len());
+        assert_eq!(
+            events.last().unwrap().line(),
+            log.events().last().unwrap().line()
+        );
+    }
+
+    #[test]
+    fn parse_modes() {
+        env_init();
+
+        let dummy = std::fs::read_to_string("logs/dummy_damage.txt").unwrap();
+        let mut lines: Vec<String> = dummy.lines().take(20).map(str::to_string).collect();
+        // A damage event that is cut after the spell, one with a corrupt
+        // amount, and garbage.
+        assert!(lines[4].contains("SPELL_DAMAGE"));
+        let cut = lines[4].find("0x8,").unwrap() + 3;
+        lines[4].truncate(cut);
+        assert!(lines[8].contains("SPELL_DAMAGE"));
+        lines[8] = lines[8].replace(",810,", ",81O,");
+        lines[9] = "4/22 14:44:40.000".to_string();
+        let text = lines.join("\n");
+
+        let lenient = Log::parse_with(&text, &ParseOptions::new().year(2020)).unwrap();
+        assert_eq!(lenient.events().len(), 17);
+        let rejected: Vec<usize> = lenient
+            .report()
+            .rejected()
+            .iter()
+            .map(|r| r.line())
+            .collect();
+        assert_eq!(rejected, vec![5, 9, 10]);
+
+        let options = ParseOptions::new().year(2020).mode(ParseMode::BestEffort);
+        let best_effort = Log::parse_with(&text, &options).unwrap();
+        assert_eq!(best_effort.events().len(), 19);
+        assert_eq!(best_effort.report().rejected().len(), 3);
+        let recovered = &best_effort.events()[4];
+        assert_eq!(recovered.line(), Some(5));
+        assert_eq!(
+            recovered.typ(),
+            EventType::Combat(Prefix::Spell, Suffix::Damage)
+        );
+        assert_eq!(
+            recovered.source(best_effort.units()).unwrap().name(),
+            "Ikiharu"
+        );
+        assert_eq!(
+            "Chain Lightning",
+            recovered.payload().spell().unwrap().name()
+        );
+        assert_eq!(None, recovered.payload().raw());
+
+        // Only the parameters of the suffix are kept as they are, while the
+        // spell is still known.
+        let corrupt = &best_effort.events()[8];
+        assert_eq!(corrupt.line(), Some(9));
+        assert_eq!("Flame Shock", corrupt.payload().spell().unwrap().name());
+        assert_eq!(None, corrupt.amount());
+        match corrupt.payload() {
+            Payload::Combat {
+                suffix: SuffixParams::Raw(fields),
+                ..
+            } => assert_eq!(
+                &["81O", "807", "4", "0", "0", "0", "nil", "nil", "nil"],
+                &fields[..]
+            ),
+            other => panic!("Expected raw suffix parameters, found {:?}", other)
+        }
+        assert_eq!(lines[8], corrupt.display(best_effort.units()).to_string());
+        assert_eq!(lines[4], recovered.display(best_effort.units()).to_string());
+
+        let options = ParseOptions::new().year(2020).mode(ParseMode::Strict);
+        let err = Log::parse_with(&text, &options).err().unwrap();
+        assert_eq!(err.line(), Some(5));
+        assert_eq!(err.typ().kind(), ParseErrorKind::InvalidArg);
+        let err = parallel::parse_chunks(text.as_bytes(), &options, 100).unwrap_err();
+        assert_eq!(err.line(), Some(5));
+        assert!(Log::parse_with(&dummy, &options).is_ok());
+    }
+
+    #[test]
+    fn parse_garbage() {
+        env_init();
+
+        // Mutate lines of all logs with a simple xorshift generator, so the
+        // test is the same every time.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut random = |max: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % max as u64) as usize
+        };
+        let pieces = [
+            ",",
+            "\"",
+            "\\",
+            " ",
+            "  ",
+            "0x",
+            "nil",
+            "-1",
+            "9999999999999999999",
+            "ä",
+            "€",
+            "\t",
+            "/",
+            ":",
+            "."
+        ];
+
+        let mut paths: Vec<_> = std::fs::read_dir("logs")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+            .collect();
+        paths.sort();
+        let mut lines = Vec::new();
+        for path in paths {
+            let text = std::fs::read_to_string(path).unwrap();
+            for line in text.lines().step_by(211) {
+                let chars: Vec<char> = line.chars().collect();
+                // Every truncation of the line.
+                for end in 0..chars.len() {
+                    lines.push(chars[..end].iter().collect::<String>());
+                }
+                // Random edits of the line.
+                for _ in 0..20 {
+                    let mut chars = chars.clone();
+                    for _ in 0..1 + random(3) {
+                        let at = random(chars.len() + 1);
+                        match random(3) {
+                            0 if at < chars.len() => {
+                                chars.remove(at);
+                            }
+                            1 => {
+                                let end = at + random(chars.len() - at + 1);
+                                chars.drain(at..end);
+                            }
+                            _ => {
+                                let piece = pieces[random(pieces.len())];
+                                chars.splice(at..at, piece.chars());
+                            }
+                        }
+                    }
+                    lines.push(chars.into_iter().collect());
+                }
+            }
+        }
+        assert!(lines.len() > 1000);
+
+        let formats = [
+            LogFormat::Legacy,
+            LogFormat::Versioned {
+                version:  9,
+                advanced: true
+            },
+            LogFormat::Versioned {
+                version:  20,
+                advanced: false
+            }
+        ];
+        let mut units = UnitTable::new();
+        for line in &lines {
+            for format in &formats {
+                let _ = Event::parse_with(line, 2020, *format, &mut units);
+            }
+        }
+
+        let text = lines.join("\n");
+        let non_empty = lines.iter().filter(|line| !line.is_empty()).count();
+        let options = ParseOptions::new().year(2020);
+        let lenient = Log::parse_with(&text, &options).unwrap();
+        assert_eq!(
+            lenient.events().len() + lenient.report().rejected().len(),
+            non_empty
+        );
+        assert!(!lenient.report().is_empty());
+
+        let best_effort =
+            Log::parse_with(&text, &options.clone().mode(ParseMode::BestEffort)).unwrap();
+        assert!(best_effort.events().len() > lenient.events().len());
+        assert_eq!(
+            best_effort.report().rejected().len(),
+            lenient.report().rejected().len()
+        );
+
+        let strict = options.clone().mode(ParseMode::Strict);
+        let err = Log::parse_with(&text, &strict).err().unwrap();
+        assert_eq!(err.line(), Some(lenient.report().rejected()[0].line()));
+        let err = parallel::parse_chunks(text.as_bytes(), &strict, 4096).unwrap_err();
+        assert_eq!(err.line(), Some(lenient.report().rejected()[0].line()));
+
+        // The views of mapped logs read the fields on their own.
+        let path = std::env::temp_dir().join(format!("parse_garbage_{}", std::process::id()));
+        std::fs::write(&path, &text).unwrap();
+        let mapped = unsafe { MappedLog::open_with(&path, options) }.expect("Unable to map log");
+        let mut views = 0;
+        for view in mapped.events().flatten() {
+            let _ = (view.spell(), view.amount(), view.payload());
+            let _ = view.to_event(&mut units);
+            views += 1;
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(views >= lenient.events().len());
+    }
+
+    #[test]
+    fn validate_log() {
+        env_init();
+
+        let log = Log::read_file("logs/utgarde_keep.txt").expect("Unable to read log");
+        assert!(log.validate().is_empty());
+
+        let dummy = std::fs::read_to_string("logs/dummy_damage.txt").unwrap();
+        let mut lines: Vec<String> = dummy.lines().take(10).map(str::to_string).collect();
+        // Line 11 goes back in time, line 12 is damage that has been cut off
+        // and line 13 renames Ikiharu.
+        lines.push(lines[0].clone());
+        let cut = lines[4].find("0x8,").unwrap() + 3;
+        lines.push(lines[4][..cut].to_string());
+        lines.push(lines[9].replace("\"Ikiharu\"", "\"Ikiharux\""));
+        lines.extend(
+            [
+                // A ghost dies, and one of the dummies is killed without dying.
+                "4/22 14:45:00.000  UNIT_DIED,0x0000000000000000,nil,0x80000000,\
+                 0xF130000000000001,\"Ghost\",0xa48",
+                "4/22 14:45:01.000  PARTY_KILL,0x000000000015A6D4,\"Ikiharu\",0x511,\
+                 0xF1300079A8000FA8,\"Grandmaster's Training Dummy\",0x10a28",
+                "4/22 14:45:02.000  PARTY_KILL,0x000000000015A6D4,\"Ikiharu\",0x511,\
+                 0xF1300079A8000FA9,\"Grandmaster's Training Dummy\",0xa28",
+                "4/22 14:45:02.300  UNIT_DIED,0x0000000000000000,nil,0x80000000,\
+                 0xF1300079A8000FA9,\"Grandmaster's Training Dummy\",0xa28"
+            ]
+            .iter()
+            .map(|line| line.to_string())
+        );
+        let text = lines.join("\n");
+        let options = ParseOptions::new().year(2020).mode(ParseMode::BestEffort);
+        let log = Log::parse_with(&text, &options).unwrap();
+
+        let findings = log.validate();
+        let found: Vec<(FindingKind, Vec<usize>)> = findings
+            .iter()
+            .map(|finding| (finding.kind(), finding.lines().clone()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (FindingKind::NameChanged, vec![1, 13]),
+                (FindingKind::TimeGoesBack, vec![10, 11]),
+                (FindingKind::DamageWithoutAmount, vec![12]),
+                (FindingKind::UnknownUnitDied, vec![14]),
+                (FindingKind::KillWithoutDeath, vec![15])
+            ]
+        );
+        assert!(findings[0].to_string().contains("\"Ikiharux\""));
+        assert!(findings[0].to_string().ends_with("(lines 1, 13)"));
     }
 
     #[test]
@@ -53,16 +1593,16 @@ mod tests {
         info!("Involved units:");
         let mut units: HashSet<Unit> = HashSet::new();
         for e in log.events() {
-            if let Some(src) = e.source() {
-                units.insert(src);
+            if let Some(src) = e.source(log.units()) {
+                units.insert(src.clone());
             }
-            if let Some(tar) = e.target() {
-                units.insert(tar);
+            if let Some(tar) = e.target(log.units()) {
+                units.insert(tar.clone());
             }
         }
 
         for u in units {
-            info!("[{:x}, \"{}\"]", u.id(), u.name());
+            info!("[{}, \"{}\"]", u.guid(), u.name());
         }
     }
 
@@ -72,13 +1612,13 @@ mod tests {
 
         let log = Log::read_file("logs/halls_of_lightning.txt").expect("Unable to read log");
 
-        let encounters = Encounter::all_encounters(log.events().clone());
+        let encounters = Encounter::all_encounters(log.events().clone(), log.units());
         info!("Number of encounters: {}", encounters.len());
 
         for e in &encounters {
             info!("{} units involved in an encounter.", e.involved().len());
             for u in e.involved() {
-                info!("[{:x}, \"{}\"]", u.id(), u.name());
+                info!("[{}, \"{}\"]", u.guid(), u.name());
             }
         }
 
@@ -94,46 +1634,64 @@ mod tests {
         info!("Involved units:");
         let mut units: HashSet<Unit> = HashSet::new();
         for e in log.events() {
-            if let Some(src) = e.source() {
-                units.insert(src);
+            if let Some(src) = e.source(log.units()) {
+                units.insert(src.clone());
             }
-            if let Some(tar) = e.target() {
-                units.insert(tar);
+            if let Some(tar) = e.target(log.units()) {
+                units.insert(tar.clone());
             }
         }
 
         for u in units {
             info!(
-                "[{:x}, \"{}\"] has dealt {} damage in total",
-                u.id(),
+                "[{}, \"{}\"] has dealt {} damage in total",
+                u.guid(),
                 u.name(),
-                extract::damage_dealt(&u, log.events().iter())
+                extract::damage_dealt(&u, log.resolved())
             );
         }
 
         // Check that the player damage amounts are in order
         assert_eq!(
-            772_442,
-            extract::damage_dealt(&Unit::new(0x137e20, "Telta".into()), log.events().iter())
+            955_902,
+            extract::damage_dealt(&Unit::new(0x137e20, "Telta".into()), log.resolved())
         );
         assert_eq!(
             32_885,
-            extract::damage_dealt(&Unit::new(0x12dc52, "Erle".into()), log.events().iter())
+            extract::damage_dealt(&Unit::new(0x12dc52, "Erle".into()), log.resolved())
         );
         assert_eq!(
-            693_396,
-            extract::damage_dealt(&Unit::new(0x160f5b, "Histera".into()), log.events().iter())
+            858_200,
+            extract::damage_dealt(&Unit::new(0x160f5b, "Histera".into()), log.resolved())
         );
         assert_eq!(
             1_624_123,
-            extract::damage_dealt(&Unit::new(0x13b13c, "Nundo".into()), log.events().iter())
+            extract::damage_dealt(&Unit::new(0x13b13c, "Nundo".into()), log.resolved())
         );
         assert_eq!(
-            1_323_749,
-            extract::damage_dealt(&Unit::new(0x117351, "Ironmate".into()), log.events().iter())
+            1_392_968,
+            extract::damage_dealt(&Unit::new(0x117351, "Ironmate".into()), log.resolved())
         );
     }
 
+    #[test]
+    fn damage_dealt_best_effort() {
+        env_init();
+
+        // The second line has been cut off after the units, so its event is
+        // kept without an amount.
+        let swing = "4/14 11:50:16.122  SWING_DAMAGE,0xF130005E49000018,\"Dark Ranger Marrah\",0xa48,0x00000000000E8806,\"Milune\",0x512";
+        let text = format!("{},317,0,1,0,0,0,nil,nil,nil\n{}\n", swing, swing);
+        let options = ParseOptions::new().year(2020).mode(ParseMode::BestEffort);
+        let log = Log::parse_with(&text, &options).unwrap();
+        assert_eq!(2, log.events().len());
+        assert_eq!(None, log.events()[1].amount());
+
+        let marrah = Unit::new(0xF130005E49000018, "Dark Ranger Marrah".into());
+        assert_eq!(317, extract::damage_dealt(&marrah, log.resolved()));
+        assert_eq!(0, extract::healing_done(&marrah, log.resolved()));
+    }
+
     #[test]
     fn healing_done() {
         env_init();
@@ -143,43 +1701,43 @@ mod tests {
         info!("Involved units:");
         let mut units: HashSet<Unit> = HashSet::new();
         for e in log.events() {
-            if let Some(src) = e.source() {
-                units.insert(src);
+            if let Some(src) = e.source(log.units()) {
+                units.insert(src.clone());
             }
-            if let Some(tar) = e.target() {
-                units.insert(tar);
+            if let Some(tar) = e.target(log.units()) {
+                units.insert(tar.clone());
             }
         }
 
         for u in units {
             info!(
-                "[{:x}, \"{}\"] has done {} healing in total",
-                u.id(),
+                "[{}, \"{}\"] has done {} healing in total",
+                u.guid(),
                 u.name(),
-                extract::healing_done(&u, log.events().iter())
+                extract::healing_done(&u, log.resolved())
             );
         }
 
         // Check that the player damage amounts are in order
         assert_eq!(
             0,
-            extract::healing_done(&Unit::new(0x137e20, "Telta".into()), log.events().iter())
+            extract::healing_done(&Unit::new(0x137e20, "Telta".into()), log.resolved())
         );
         assert_eq!(
             3_622_665,
-            extract::healing_done(&Unit::new(0x12dc52, "Erle".into()), log.events().iter())
+            extract::healing_done(&Unit::new(0x12dc52, "Erle".into()), log.resolved())
         );
         assert_eq!(
             202_990,
-            extract::healing_done(&Unit::new(0x160f5b, "Histera".into()), log.events().iter())
+            extract::healing_done(&Unit::new(0x160f5b, "Histera".into()), log.resolved())
         );
         assert_eq!(
             0,
-            extract::healing_done(&Unit::new(0x13b13c, "Nundo".into()), log.events().iter())
+            extract::healing_done(&Unit::new(0x13b13c, "Nundo".into()), log.resolved())
         );
         assert_eq!(
             14_750,
-            extract::healing_done(&Unit::new(0x117351, "Ironmate".into()), log.events().iter())
+            extract::healing_done(&Unit::new(0x117351, "Ironmate".into()), log.resolved())
         );
     }
 }
@@ -1,55 +1,293 @@
+use crate::cache;
+use crate::compression::{self, Compression};
 use crate::event::Event;
 use crate::filter::{Filter, Filterable};
+use crate::parser::{self, ParseOptions};
+use crate::unit_table::UnitTable;
 use crate::FilteredEvents;
 use bitvec::prelude::*;
-use std::fs::File;
-use std::io::Read;
-use std::mem;
-use std::path::Path;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 pub enum ParseErrorType {
-    IOErr, // TODO: Add io::Error to this
+    /// Reading the log failed. The error is shared, since `io::Error` cannot
+    /// be cloned.
+    IOErr(Arc<io::Error>),
     WrongHeadLength,
     WrongTimeFormat,
     InvalidArg,
     UnknownEventType(String)
 }
 
+/// The kind of a `ParseErrorType`, without any of the data it carries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ParseErrorKind {
+    IOErr,
+    WrongHeadLength,
+    WrongTimeFormat,
+    InvalidArg,
+    UnknownEventType
+}
+
 #[derive(Clone, Debug)]
 pub struct ParseError {
-    typ: ParseErrorType,
-    col: usize
+    typ:  ParseErrorType,
+    col:  usize,
+    /// The line of the log the error has been found in, if the error ended
+    /// the parsing of the whole log.
+    line: Option<usize>
+}
+
+/// A line of the log that could not be parsed into an event.
+#[derive(Clone, Debug)]
+pub struct RejectedLine {
+    /// The number of the line, starting at one.
+    line:  usize,
+    /// The text of the line as it has been found in the log.
+    raw:   String,
+    error: ParseError
+}
+
+/// Everything that went wrong while parsing a log.
+#[derive(Clone, Debug, Default)]
+pub struct ParseReport {
+    rejected: Vec<RejectedLine>,
+    counts:   HashMap<ParseErrorKind, usize>
+}
+
+/// Where the lines of a log can be read again. The text of logs parsed from a
+/// string is shared between the log and the logs taken from it.
+#[derive(Clone, Debug)]
+enum Source {
+    None,
+    Text(Arc<str>),
+    File(PathBuf)
 }
 
 pub struct Log {
-    events: Vec<Event>
+    events: Vec<Event>,
+    units:  UnitTable,
+    report: ParseReport,
+    source: Source
 }
 
 impl Log {
+    /// Read the log from a file, inferring the year of the events from the time
+    /// the file was last modified. Files compressed with gzip or zstd are
+    /// decompressed while reading, unless the feature of the same name is
+    /// disabled, in which case reading them fails.
     pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Log, ParseError> {
-        let mut file = match File::open(path.as_ref()) {
+        Log::read_file_with(path, ParseOptions::default())
+    }
+
+    /// Read the log from a file, using the given options. If they do not
+    /// contain a year, it is inferred from the time the file was last
+    /// modified.
+    pub fn read_file_with<P: AsRef<Path>>(
+        path: P,
+        options: ParseOptions
+    ) -> Result<Log, ParseError> {
+        let options = options.for_file(path.as_ref());
+
+        let file = match compression::open(path.as_ref()) {
             Ok(file) => file,
             Err(err) => {
                 error!("Could not open log file {:?}: {:?}", path.as_ref(), err);
-                return Err(ParseError::new(ParseErrorType::IOErr, 0)); // XXX: This is stupid. This should not take a column
+                return Err(err.into());
             }
         };
 
-        let mut string = String::new();
-        match file.read_to_string(&mut string) {
-            Ok(_) => {}
+        match parser::parse_reader(file, &options) {
+            Ok((events, units, report)) => Ok(Log::from_file(path.as_ref(), events, units, report)),
             Err(err) => {
-                error!("Could not read log file {:?}: {:?}", path.as_ref(), err);
-                return Err(ParseError::new(ParseErrorType::IOErr, 0));
+                error!("Could not read log file {:?}: {}", path.as_ref(), err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Parse a log from a string, using the given options. Only fails in
+    /// `ParseMode::Strict`. The string is kept, so that the lines of the
+    /// events can be found with `raw_line`.
+    pub fn parse_with(string: &str, options: &ParseOptions) -> Result<Log, ParseError> {
+        let (events, units, report) = parser::parse_with(string, options)?;
+        Ok(Log {
+            events,
+            units,
+            report,
+            source: Source::Text(string.into())
+        })
+    }
+
+    /// A log that has been parsed from the file at the given path.
+    pub(crate) fn from_file(
+        path: &Path,
+        events: Vec<Event>,
+        units: UnitTable,
+        report: ParseReport
+    ) -> Log {
+        Log {
+            events,
+            units,
+            report,
+            source: Source::File(path.to_path_buf())
+        }
+    }
+
+    /// A log of the given events, which have not been parsed from any lines.
+    /// Their units must be part of the table.
+    pub fn from_events(events: Vec<Event>, units: UnitTable) -> Log {
+        Log {
+            events,
+            units,
+            report: ParseReport::new(),
+            source: Source::None
+        }
+    }
+
+    /// A log of some of the events of this log, whose lines can still be
+    /// found with `raw_line` and `raw_line_in`. Its table only holds the units
+    /// these events refer to.
+    pub(crate) fn sub_log(&self, mut events: Vec<Event>) -> Log {
+        let units = self.units.sub_table(&mut events);
+        Log {
+            events,
+            units,
+            report: ParseReport::new(),
+            source: self.source.clone()
+        }
+    }
+
+    /// Read the log from a file like `read_file`, unless a cache of it that
+    /// is newer than the file exists at `Log::cache_path`. In that case, the
+    /// log is loaded from the cache instead. Otherwise, the cache is written
+    /// after the log has been parsed.
+    pub fn read_file_cached<P: AsRef<Path>>(path: P) -> Result<Log, ParseError> {
+        let path = path.as_ref();
+        let cache = Log::cache_path(path);
+
+        let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified());
+        if let (Ok(source), Ok(cached)) = (modified(path), modified(&cache)) {
+            if cached >= source {
+                match Log::load_cache(&cache) {
+                    Ok(log) => {
+                        return Ok(Log {
+                            source: Source::File(path.to_path_buf()),
+                            ..log
+                        })
+                    }
+                    Err(err) => warn!("Ignoring cache {:?}: {}", cache, err)
+                }
             }
         }
 
-        Log::from_str(&string)
+        let log = Log::read_file(path)?;
+        if let Err(err) = log.save_cache(&cache) {
+            warn!("Could not write cache {:?}: {}", cache, err);
+        }
+        Ok(log)
+    }
+
+    /// The path of the cache `read_file_cached` uses for the log at the given
+    /// path, which is the same path with `.cache` appended.
+    pub fn cache_path<P: AsRef<Path>>(path: P) -> PathBuf {
+        let mut cache = path.as_ref().as_os_str().to_owned();
+        cache.push(".cache");
+        PathBuf::from(cache)
+    }
+
+    /// Write the events and the report of the log into a binary cache file,
+    /// which can be loaded much faster than the log can be parsed again.
+    pub fn save_cache<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, cache::encode(&self.events, &self.units, &self.report))
+    }
+
+    /// Load a log from a cache file written by `save_cache`. Caches written in
+    /// another version of the format cannot be loaded.
+    pub fn load_cache<P: AsRef<Path>>(path: P) -> Result<Log, ParseError> {
+        let (events, units, report) = cache::decode(&fs::read(path)?)?;
+        Ok(Log {
+            events,
+            units,
+            report,
+            source: Source::None
+        })
     }
 
     pub fn events(&self) -> &Vec<Event> { &self.events }
+
+    /// The units the events of the log refer to.
+    pub fn units(&self) -> &UnitTable { &self.units }
+
+    /// The events paired with the units they refer to, like the functions of
+    /// `extract` take them.
+    pub fn resolved(&self) -> impl Iterator<Item = (&Event, &UnitTable)> + Clone {
+        self.events.iter().map(move |event| (event, &self.units))
+    }
+
+    /// The line the event has been read from, without its line ending, for
+    /// instance to check a number that looks wrong. Returns `None` if the
+    /// event has not been read from this log, or the lines of the log are not
+    /// known, like for merged logs or logs loaded from a cache file. Files
+    /// are read again, so they must not have changed since.
+    pub fn raw_line(&self, event: &Event) -> Option<String> {
+        let offset = event.offset()?;
+        let mut line = Vec::new();
+        match &self.source {
+            Source::None => return None,
+            Source::Text(text) => {
+                let rest = text.as_bytes().get(usize::try_from(offset).ok()?..)?;
+                let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+                line.extend_from_slice(&rest[..end]);
+            }
+            Source::File(path) => {
+                let mut file = File::open(path).ok()?;
+                let mut magic = [0; 4];
+                let len = file.read(&mut magic).ok()?;
+                let mut reader: Box<dyn BufRead> =
+                    if Compression::detect(&magic[..len]) == Compression::None {
+                        file.seek(SeekFrom::Start(offset)).ok()?;
+                        Box::new(BufReader::new(file))
+                    }
+                    else {
+                        // Compressed files have to be decompressed up to the line.
+                        let mut reader = compression::open(path).ok()?;
+                        io::copy(&mut reader.by_ref().take(offset), &mut io::sink()).ok()?;
+                        reader
+                    };
+                reader.read_until(b'\n', &mut line).ok()?;
+            }
+        }
+
+        while line.last().is_some_and(|&b| b == b'\n' || b == b'\r') {
+            line.pop();
+        }
+        Some(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    /// The line the event has been read from in the string the log has been
+    /// parsed from, without its line ending. Unlike `raw_line`, this works for
+    /// logs of the string that have been loaded from a cache file as well.
+    /// Returns `None` if the event has not been read from a log.
+    pub fn raw_line_in<'a>(&self, event: &Event, text: &'a str) -> Option<&'a str> {
+        let rest = text.get(usize::try_from(event.offset()?).ok()?..)?;
+        let line = rest.split('\n').next()?;
+        Some(line.strip_suffix('\r').unwrap_or(line))
+    }
+
+    /// The lines that have been rejected while parsing this log.
+    pub fn report(&self) -> &ParseReport { &self.report }
 }
 
 /// Parse a warcraft log from a string into the log object
@@ -57,27 +295,7 @@ impl FromStr for Log {
     type Err = ParseError;
 
     fn from_str(string: &str) -> Result<Log, Self::Err> {
-        let mut events = Vec::new();
-        for (e, l) in string.lines().enumerate() {
-            // TODO: At the moment, errors are only logged. They should instead be
-            // made accessible via the API.
-            match Event::from_str(l) {
-                Ok(event) => events.push(event),
-                Err(err) => error!(
-                    "Error parsing, line {}, column {}: {:?}",
-                    e + 1,
-                    err.col(),
-                    err.typ()
-                )
-            }
-        }
-
-        info!(
-            "Loaded {} events successfully into memory ({} KBytes)",
-            events.len(),
-            mem::size_of::<Event>() * events.len() / 1024
-        );
-        Ok(Log { events })
+        Log::parse_with(string, &ParseOptions::default())
     }
 }
 
@@ -95,10 +313,175 @@ impl<'a> Filterable for &'a Log {
     }
 }
 
+impl ParseErrorType {
+    pub fn kind(&self) -> ParseErrorKind {
+        match self {
+            ParseErrorType::IOErr(_) => ParseErrorKind::IOErr,
+            ParseErrorType::WrongHeadLength => ParseErrorKind::WrongHeadLength,
+            ParseErrorType::WrongTimeFormat => ParseErrorKind::WrongTimeFormat,
+            ParseErrorType::InvalidArg => ParseErrorKind::InvalidArg,
+            ParseErrorType::UnknownEventType(_) => ParseErrorKind::UnknownEventType
+        }
+    }
+}
+
+impl fmt::Display for ParseErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseErrorType::IOErr(err) => write!(f, "unable to read log: {}", err),
+            ParseErrorType::WrongHeadLength => write!(f, "malformed timestamp or event type"),
+            ParseErrorType::WrongTimeFormat => write!(f, "malformed timestamp"),
+            ParseErrorType::InvalidArg => write!(f, "invalid argument"),
+            ParseErrorType::UnknownEventType(typ) => write!(f, "unknown event type {}", typ)
+        }
+    }
+}
+
 impl ParseError {
-    pub fn new(typ: ParseErrorType, col: usize) -> ParseError { ParseError { typ, col } }
+    pub fn new(typ: ParseErrorType, col: usize) -> ParseError {
+        ParseError {
+            typ,
+            col,
+            line: None
+        }
+    }
+
+    /// The same error, found in the given line of the log.
+    pub(crate) fn at_line(mut self, line: usize) -> ParseError {
+        self.line = Some(line);
+        self
+    }
 
     pub fn typ(&self) -> ParseErrorType { self.typ.clone() }
 
     pub fn col(&self) -> usize { self.col }
+
+    /// The number of the line, starting at one, when parsing a log has been
+    /// stopped by this error in `ParseMode::Strict`. Errors of single lines
+    /// do not know their line.
+    pub fn line(&self) -> Option<usize> { self.line }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.typ {
+            ParseErrorType::IOErr(_) => write!(f, "{}", self.typ),
+            typ => match self.line {
+                Some(line) => write!(f, "{} at column {} of line {}", typ, self.col, line),
+                None => write!(f, "{} at column {}", typ, self.col)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.typ {
+            ParseErrorType::IOErr(err) => Some(err.as_ref()),
+            _ => None
+        }
+    }
+}
+
+/// The serialized form of a `ParseError`.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct ParseErrorRepr {
+    kind:   ParseErrorKind,
+    col:    usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    line:   Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    detail: Option<String>
+}
+
+/// Parse errors are serialized as an object with the `kind` of the error, like
+/// `"InvalidArg"`, the `col`, the `line` if it is known and, for read errors
+/// and unknown event types, the error message or the name of the event type
+/// as `detail`.
+#[cfg(feature = "serde")]
+impl Serialize for ParseError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let detail = match &self.typ {
+            ParseErrorType::IOErr(err) => Some(err.to_string()),
+            ParseErrorType::UnknownEventType(typ) => Some(typ.clone()),
+            _ => None
+        };
+
+        ParseErrorRepr {
+            kind: self.typ.kind(),
+            col: self.col,
+            line: self.line,
+            detail
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for ParseError {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<ParseError, D::Error> {
+        let repr = ParseErrorRepr::deserialize(deserializer)?;
+        let detail = repr.detail.unwrap_or_default();
+        let typ = match repr.kind {
+            ParseErrorKind::IOErr => ParseErrorType::IOErr(Arc::new(io::Error::other(detail))),
+            ParseErrorKind::WrongHeadLength => ParseErrorType::WrongHeadLength,
+            ParseErrorKind::WrongTimeFormat => ParseErrorType::WrongTimeFormat,
+            ParseErrorKind::InvalidArg => ParseErrorType::InvalidArg,
+            ParseErrorKind::UnknownEventType => ParseErrorType::UnknownEventType(detail)
+        };
+
+        Ok(ParseError {
+            typ,
+            col: repr.col,
+            line: repr.line
+        })
+    }
+}
+
+/// Errors while reading are not bound to a column, so it is always zero.
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> ParseError {
+        ParseError::new(ParseErrorType::IOErr(Arc::new(err)), 0)
+    }
+}
+
+impl RejectedLine {
+    pub fn line(&self) -> usize { self.line }
+
+    pub fn col(&self) -> usize { self.error.col() }
+
+    pub fn raw(&self) -> &String { &self.raw }
+
+    pub fn typ(&self) -> ParseErrorType { self.error.typ() }
+
+    pub fn error(&self) -> &ParseError { &self.error }
+}
+
+impl ParseReport {
+    pub fn new() -> ParseReport { ParseReport::default() }
+
+    /// Record that the given line, counting from one, has been rejected.
+    pub fn reject(&mut self, line: usize, raw: &str, error: ParseError) {
+        *self.counts.entry(error.typ.kind()).or_insert(0) += 1;
+        self.rejected.push(RejectedLine {
+            line,
+            raw: raw.to_string(),
+            error
+        });
+    }
+
+    /// All rejected lines in the order they appeared in the log.
+    pub fn rejected(&self) -> &Vec<RejectedLine> { &self.rejected }
+
+    /// The number of lines that have been rejected with the given kind of
+    /// error.
+    pub fn count(&self, kind: ParseErrorKind) -> usize {
+        self.counts.get(&kind).copied().unwrap_or(0)
+    }
+
+    pub fn counts(&self) -> &HashMap<ParseErrorKind, usize> { &self.counts }
+
+    /// Check if every line of the log has been parsed successfully.
+    pub fn is_empty(&self) -> bool { self.rejected.is_empty() }
 }
@@ -0,0 +1,395 @@
+//! Reading logs straight from a memory mapped file. The events borrow the
+//! names of their units and spells from the mapping instead of copying them,
+//! so even the largest logs can be scanned without reading them into memory
+//! first. Only the list of the fields of every line is allocated.
+
+use crate::compression::Compression;
+use crate::event::{parse_head, unit_cols, unit_parts, Event, EventType, Prefix, Suffix};
+use crate::format::LogFormat;
+use crate::guid::Guid;
+use crate::log::{Log, ParseError, ParseErrorType, ParseReport};
+use crate::parser::{self, ParseOptions, Timeline};
+use crate::payload::{arg, hex_arg, param_cols, Damage, Heal, Payload, Spell};
+use crate::tokenizer::{tokenize, Field};
+use crate::unit::Unit;
+use crate::unit_flags::UnitFlags;
+use crate::unit_table::UnitTable;
+use chrono::NaiveDateTime;
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A log file that has been mapped into memory.
+pub struct MappedLog {
+    map:     Mmap,
+    /// The lines that are not valid UTF-8 by the position of their start,
+    /// with the invalid characters replaced like `EventReader` does. Their
+    /// events borrow from here instead of from the mapping.
+    lossy:   HashMap<u64, String>,
+    path:    PathBuf,
+    options: ParseOptions
+}
+
+/// Iterates over the events of a `MappedLog`, like `EventReader` does for
+/// other sources.
+pub struct MappedEvents<'a> {
+    rest: &'a [u8],
+    lossy: &'a HashMap<u64, String>,
+    timeline: Timeline,
+    format: LogFormat,
+    /// The current line without its line ending.
+    line: &'a [u8],
+    line_number: usize,
+    /// The position of the start of the current line in bytes.
+    offset: u64,
+    /// The position of the start of the next line in bytes.
+    next_offset: u64
+}
+
+/// An event that borrows its line from a `MappedLog`. Only the time, the type
+/// and the units are read while iterating, everything else when it is asked
+/// for.
+#[derive(Clone, Debug)]
+pub struct EventView<'a> {
+    raw:    &'a str,
+    fields: Vec<Field<'a>>,
+    time:   NaiveDateTime,
+    typ:    EventType,
+    /// The format of the log at the line of the event.
+    format: LogFormat,
+    source: Option<UnitView<'a>>,
+    target: Option<UnitView<'a>>,
+    line:   usize,
+    offset: u64
+}
+
+/// A unit of an `EventView`, whose name is borrowed from the log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnitView<'a> {
+    guid:  Guid,
+    name:  Cow<'a, str>,
+    flags: UnitFlags
+}
+
+/// A spell of an `EventView`, whose name is borrowed from the log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpellView<'a> {
+    id:     u32,
+    name:   Cow<'a, str>,
+    school: u32
+}
+
+/// Split off the first line of the bytes, returning it without its line
+/// ending together with its length including the line ending.
+fn split_line(bytes: &[u8]) -> (&[u8], usize) {
+    let len = bytes
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |end| end + 1);
+    let line = &bytes[..len];
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    (line.strip_suffix(b"\r").unwrap_or(line), len)
+}
+
+/// Decode the lines that are not valid UTF-8, replacing the invalid
+/// characters.
+fn decode_lossy(bytes: &[u8]) -> HashMap<u64, String> {
+    let mut lossy = HashMap::new();
+    if std::str::from_utf8(bytes).is_ok() {
+        return lossy;
+    }
+
+    let mut rest = bytes;
+    let mut offset = 0;
+    while !rest.is_empty() {
+        let (line, len) = split_line(rest);
+        if std::str::from_utf8(line).is_err() {
+            lossy.insert(offset, String::from_utf8_lossy(line).into_owned());
+        }
+        rest = &rest[len..];
+        offset += len as u64;
+    }
+
+    lossy
+}
+
+impl MappedLog {
+    /// Map the log file into memory, inferring the year of the events from the
+    /// time the file was last modified like `Log::read_file`.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be truncated or changed in place while it is mapped,
+    /// which would change the events borrowing from it or even crash the
+    /// program when they are read. The client only ever appends to its log,
+    /// which does not affect the mapped part, but other programs writing to
+    /// the file have to be ruled out by the caller.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> Result<MappedLog, ParseError> {
+        MappedLog::open_with(path, ParseOptions::default())
+    }
+
+    /// Map the log file into memory, using the given options. Compressed logs
+    /// cannot be mapped, since they have to be decompressed while reading.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be truncated or changed in place while it is mapped,
+    /// see `MappedLog::open`.
+    pub unsafe fn open_with<P: AsRef<Path>>(
+        path: P,
+        options: ParseOptions
+    ) -> Result<MappedLog, ParseError> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let map = Mmap::map(&file)?;
+        if Compression::detect(&map) != Compression::None {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "compressed logs cannot be mapped"
+            )
+            .into());
+        }
+
+        Ok(MappedLog {
+            lossy: decode_lossy(&map),
+            map,
+            path: path.to_path_buf(),
+            options: options.for_file(path)
+        })
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// All events of the log, in the order of their lines.
+    pub fn events(&self) -> MappedEvents<'_> {
+        MappedEvents {
+            rest: &self.map,
+            lossy: &self.lossy,
+            timeline: Timeline::new(&self.options),
+            format: LogFormat::Legacy,
+            line: &[],
+            line_number: 0,
+            offset: 0,
+            next_offset: 0
+        }
+    }
+
+    /// Parse the whole log into owned events, exactly like `Log::read_file`
+    /// would, but without reading the file into memory first.
+    pub fn to_log(&self) -> Result<Log, ParseError> {
+        let (events, units, report) = parser::parse_bytes(&self.map, &self.options)?;
+        Ok(Log::from_file(&self.path, events, units, report))
+    }
+}
+
+impl<'a> MappedEvents<'a> {
+    /// The number of the line that has been read last, starting at one.
+    pub fn line_number(&self) -> usize { self.line_number }
+
+    /// The position in bytes of the start of the line that has been read last.
+    pub fn offset(&self) -> u64 { self.offset }
+
+    /// The text of the line that has been read last, without the line ending.
+    /// Invalid characters are replaced.
+    pub fn line(&self) -> Cow<'a, str> { String::from_utf8_lossy(self.line) }
+
+    /// Parse the remaining events into the report of the rejected lines and
+    /// the owned events, whose units are added to the table.
+    pub fn to_events(mut self, units: &mut UnitTable) -> (Vec<Event>, ParseReport) {
+        let mut events = Vec::new();
+        let mut report = ParseReport::new();
+        while let Some(view) = self.next() {
+            match view.and_then(|view| view.to_event(units)) {
+                Ok(event) => events.push(event),
+                Err(err) => report.reject(self.line_number, &self.line(), err)
+            }
+        }
+
+        (events, report)
+    }
+}
+
+impl<'a> Iterator for MappedEvents<'a> {
+    type Item = Result<EventView<'a>, ParseError>;
+
+    /// Read the next event. Empty lines are skipped, while invalid characters
+    /// are replaced like `EventReader` does.
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.rest.is_empty() {
+            let (line, len) = split_line(self.rest);
+            self.rest = &self.rest[len..];
+            self.line = line;
+
+            self.line_number += 1;
+            self.offset = self.next_offset;
+            self.next_offset += len as u64;
+            if self.line.is_empty() {
+                continue;
+            }
+
+            // Lines that are not valid UTF-8 have been decoded when mapping the
+            // log.
+            let line = match std::str::from_utf8(self.line) {
+                Ok(line) => line,
+                Err(_) => &self.lossy[&self.offset]
+            };
+            let view = EventView::parse(line, &mut self.timeline, &mut self.format);
+            return Some(view.map(|mut view| {
+                view.line = self.line_number;
+                view.offset = self.offset;
+                view
+            }));
+        }
+
+        None
+    }
+}
+
+impl<'a> EventView<'a> {
+    /// Read the head and the units of the line, placing the event on the
+    /// timeline and switching the format if the line is the header of a
+    /// versioned log.
+    fn parse(
+        raw: &'a str,
+        timeline: &mut Timeline,
+        format: &mut LogFormat
+    ) -> Result<EventView<'a>, ParseError> {
+        let fields = tokenize(raw)?;
+        let (time, typ) = parse_head(&fields)?;
+        let line_format = *format;
+        if typ == EventType::CombatLogVersion {
+            *format = LogFormat::from_header(&fields)?;
+        }
+
+        let (source, target) = match unit_cols(&typ, line_format) {
+            Some((source, target)) => (
+                UnitView::parse(&fields, source),
+                UnitView::parse(&fields, target)
+            ),
+            None => (None, None)
+        };
+
+        Ok(EventView {
+            raw,
+            time: timeline
+                .place(time)
+                .ok_or_else(|| ParseError::new(ParseErrorType::WrongTimeFormat, 0))?,
+            typ,
+            format: line_format,
+            source,
+            target,
+            fields,
+            line: 0,
+            offset: 0
+        })
+    }
+
+    /// The line of the event, without the line ending. Invalid characters are
+    /// replaced.
+    pub fn raw(&self) -> &'a str { self.raw }
+
+    pub fn time(&self) -> NaiveDateTime { self.time }
+
+    pub fn typ(&self) -> EventType { self.typ.clone() }
+
+    pub fn source(&self) -> Option<&UnitView<'a>> { self.source.as_ref() }
+
+    pub fn target(&self) -> Option<&UnitView<'a>> { self.target.as_ref() }
+
+    /// The number of the line of the event, starting at one.
+    pub fn line(&self) -> usize { self.line }
+
+    /// The position in bytes of the start of the line of the event.
+    pub fn offset(&self) -> u64 { self.offset }
+
+    /// The prefix and the suffix of a combat event, with the columns their
+    /// parameters start at.
+    fn combat(&self) -> Option<((Prefix, usize), (Suffix, usize))> {
+        match self.typ {
+            EventType::Combat(prefix, suffix) => {
+                let (prefix_col, suffix_col) = param_cols(prefix, suffix, self.format);
+                Some(((prefix, prefix_col), (suffix, suffix_col)))
+            }
+            _ => None
+        }
+    }
+
+    /// The spell that caused the event, if any.
+    pub fn spell(&self) -> Option<SpellView<'a>> {
+        match self.combat()? {
+            ((Prefix::Swing, _), _) | ((Prefix::Environmental, _), _) => None,
+            ((_, col), _) => SpellView::parse(&self.fields, col)
+        }
+    }
+
+    /// The amount of damage or healing, or `None` if the event is of neither,
+    /// like `Event::amount`.
+    pub fn amount(&self) -> Option<u64> {
+        match self.combat()? {
+            (_, (Suffix::Damage, col)) => Damage::parse(&self.fields, col, self.format)
+                .ok()
+                .map(|damage| damage.amount()),
+            (_, (Suffix::Heal, col)) => Heal::parse(&self.fields, col, self.format)
+                .ok()
+                .map(|heal| heal.amount()),
+            _ => None
+        }
+    }
+
+    /// Parse the payload of the event, which fails if the line is malformed
+    /// after the units.
+    pub fn payload(&self) -> Result<Payload, ParseError> {
+        Payload::parse(&self.typ, &self.fields, self.format)
+    }
+
+    /// Parse the line into an owned event, adding its units to the table.
+    pub fn to_event(&self, units: &mut UnitTable) -> Result<Event, ParseError> {
+        let (mut event, _) =
+            Event::parse_recovering(self.raw, self.format, units, false, |_| Some(self.time))?;
+        event.set_position(self.line, self.offset);
+        Ok(event)
+    }
+}
+
+impl<'a> UnitView<'a> {
+    fn parse(fields: &[Field<'a>], col: usize) -> Option<UnitView<'a>> {
+        let (guid, name, flags) = unit_parts(fields, col)?;
+        Some(UnitView {
+            guid,
+            name: name.text()?,
+            flags
+        })
+    }
+
+    pub fn guid(&self) -> &Guid { &self.guid }
+
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn flags(&self) -> UnitFlags { self.flags }
+
+    /// Copy the unit, for instance to look it up in a `UnitTable`.
+    pub fn to_unit(&self) -> Unit {
+        Unit::with_guid(self.guid.clone(), self.name.to_string(), self.flags)
+    }
+}
+
+impl<'a> SpellView<'a> {
+    fn parse(fields: &[Field<'a>], col: usize) -> Option<SpellView<'a>> {
+        Some(SpellView {
+            id:     arg(fields, col).ok()?,
+            name:   fields.get(col + 1)?.text()?,
+            school: hex_arg(fields, col + 2).ok()?
+        })
+    }
+
+    pub fn id(&self) -> u32 { self.id }
+
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn school(&self) -> u32 { self.school }
+
+    pub fn to_spell(&self) -> Spell { Spell::new(self.id, self.name.as_ref().into(), self.school) }
+}
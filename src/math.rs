@@ -21,7 +21,7 @@ pub fn simpsons_d_of_one(probabilities: &[f64]) -> f64 {
     assert!(probabilities.len() > 1);
 
     let factor = probabilities.len() as f64 / (probabilities.len() - 1) as f64;
-    factor * simpsons_d(&probabilities)
+    factor * simpsons_d(probabilities)
 }
 
 /// Calculate Leti's D. Keep in mind, to enter the probabilities in ranked order
@@ -46,7 +46,7 @@ pub fn letis_d_of_one(ranked_probabilities: &[f64]) -> f64 {
     assert!(ranked_probabilities.len() > 1);
 
     let factor = 4. / (ranked_probabilities.len() - 1) as f64;
-    factor * letis_d(&ranked_probabilities)
+    factor * letis_d(ranked_probabilities)
 }
 
 pub fn entropy(probabilities: &[f64]) -> f64 {
@@ -0,0 +1,174 @@
+//! Merging logs of the same fight that have been recorded by different
+//! clients. Every client only records what happens in its range and uses its
+//! own clock, so the logs overlap only partly and their times are offset
+//! against each other.
+
+use crate::event::Event;
+use crate::log::Log;
+use crate::unit::Unit;
+use crate::unit_table::UnitTable;
+use chrono::{Duration, NaiveDateTime};
+use std::collections::HashMap;
+
+/// Events of the same kind that occur more often than this in a log are not
+/// used to find the clock skew, since pairing all of them would mostly produce
+/// wrong offsets.
+const MAX_CANDIDATES: usize = 4;
+
+/// How far apart the times of the same event may be in both logs after the
+/// skew has been corrected, in milliseconds. Clients receive events with
+/// differing latencies, so the times never match exactly.
+const TOLERANCE: i64 = 500;
+
+/// The result of `Log::merge`.
+pub struct MergedLog {
+    log: Log,
+    skew: Option<Duration>,
+    duplicates: usize
+}
+
+/// Everything about an event except for its time and the flags of its units.
+/// The flags describe the units relative to the client that has recorded the
+/// log, so the same player is the player of one log and in the party of the
+/// other.
+fn key(event: &Event, units: &UnitTable) -> String {
+    let unit = |unit: Option<&Unit>| unit.map(|unit| (unit.guid(), unit.name().to_string()));
+    format!(
+        "{} {:?} {:?} {:?}",
+        event.typ(),
+        unit(event.source(units)),
+        unit(event.target(units)),
+        event.payload()
+    )
+}
+
+fn index(log: &Log) -> HashMap<String, Vec<NaiveDateTime>> {
+    let mut index: HashMap<String, Vec<NaiveDateTime>> = HashMap::new();
+    for event in log.events() {
+        index
+            .entry(key(event, log.units()))
+            .or_default()
+            .push(event.time(log.units()));
+    }
+    index
+}
+
+/// Find the offset of the clock of the other log, in milliseconds. Every pair
+/// of shared events suggests an offset, and the offsets of the pairs that
+/// really are the same event cluster around the skew.
+fn find_skew(
+    own: &HashMap<String, Vec<NaiveDateTime>>,
+    other: &HashMap<String, Vec<NaiveDateTime>>
+) -> Option<i64> {
+    let mut offsets = Vec::new();
+    for (key, times) in own {
+        let other_times = match other.get(key) {
+            Some(other_times) => other_times,
+            None => continue
+        };
+        if times.len() > MAX_CANDIDATES || other_times.len() > MAX_CANDIDATES {
+            continue;
+        }
+
+        for time in times {
+            for other_time in other_times {
+                offsets.push((*other_time - *time).num_milliseconds());
+            }
+        }
+    }
+    offsets.sort_unstable();
+
+    // The window of the tolerance containing the most offsets.
+    let mut best = None;
+    let mut start = 0;
+    for end in 0..offsets.len() {
+        while offsets[end] - offsets[start] > TOLERANCE {
+            start += 1;
+        }
+        if best.is_none_or(|(s, e): (usize, usize)| e - s < end - start) {
+            best = Some((start, end));
+        }
+    }
+
+    best.map(|(start, end)| offsets[(start + end) / 2])
+}
+
+impl Log {
+    /// Merge the events of another log of the same time into this one. The
+    /// clock skew between both logs is detected from the events they share,
+    /// and the times of the other log are corrected by it. Events that are
+    /// found in both logs are only kept once.
+    ///
+    /// The rejected lines of the logs are not carried over, since their line
+    /// numbers refer to the original files.
+    pub fn merge(&self, other: &Log) -> MergedLog {
+        let mut own = index(self);
+        let skew = find_skew(&own, &index(other));
+
+        let offset = Duration::milliseconds(skew.unwrap_or(0));
+        let mut events = self.events().clone();
+        let mut units = self.units().clone();
+        let mut duplicates = 0;
+        for event in other.events() {
+            let time = event.time(other.units()) - offset;
+
+            // Every event of this log can only be the duplicate of one event
+            // of the other log, so matched times are removed.
+            let times = own.entry(key(event, other.units())).or_default();
+            let nearest = times
+                .iter()
+                .enumerate()
+                .map(|(i, own_time)| (i, (time - *own_time).num_milliseconds().abs()))
+                .filter(|&(_, diff)| diff <= TOLERANCE)
+                .min_by_key(|&(_, diff)| diff);
+            if let Some((i, _)) = nearest {
+                times.swap_remove(i);
+                duplicates += 1;
+                continue;
+            }
+
+            let mut intern = |unit: Option<&Unit>| unit.map(|unit| units.intern(unit.clone()));
+            let source = intern(event.source(other.units()));
+            let target = intern(event.target(other.units()));
+            let payload = event.payload().clone();
+            match Event::new(time, event.typ(), source, target, payload, &mut units) {
+                Ok(mut added) => {
+                    added.set_extras(event.extras());
+                    added.move_extras(other.units(), &mut units);
+                    events.push(added)
+                }
+                Err(_) => warn!(
+                    "Dropping event at {}, too far from the start of the log",
+                    time
+                )
+            }
+        }
+
+        // The sort is stable, so events of the same time stay in the order of
+        // their logs.
+        events.sort_by_key(|event| event.time(&units));
+
+        MergedLog {
+            log: Log::from_events(events, units),
+            skew: skew.map(Duration::milliseconds),
+            duplicates
+        }
+    }
+}
+
+impl MergedLog {
+    /// The merged events, with the times of the log `merge` has been called
+    /// on.
+    pub fn log(&self) -> &Log { &self.log }
+
+    pub fn into_log(self) -> Log { self.log }
+
+    /// How far the clock of the other log was ahead, or `None` if the logs
+    /// have no events in common. In that case, their times are taken as they
+    /// are.
+    pub fn skew(&self) -> Option<Duration> { self.skew }
+
+    /// The number of events of the other log that have been dropped, since
+    /// they were already part of this log.
+    pub fn duplicates(&self) -> usize { self.duplicates }
+}
@@ -0,0 +1,229 @@
+//! Parsing large logs on multiple threads. The log is split into chunks at
+//! line boundaries, which are parsed on the thread pool and stitched back
+//! together in their original order.
+//!
+//! The year, the format and the start of the log carry over from one line to
+//! the next, so every chunk is parsed assuming the state the log is in after
+//! its first event. That is almost always right. The chunks for which it turns
+//! out to be wrong are parsed again once the state at their start is known, so
+//! the result is the same as when parsing the log sequentially.
+
+use crate::event::Event;
+use crate::format::LogFormat;
+use crate::log::{ParseError, ParseReport};
+use crate::parser::{EventParser, ParseMode, ParseOptions, Timeline};
+use crate::payload::Names;
+use crate::reader::EventReader;
+use crate::unit_table::{UnitRef, UnitTable};
+use chrono::NaiveDateTime;
+use rayon::prelude::*;
+use std::mem;
+
+/// How many chunks every thread gets, so that threads that are done early can
+/// take over the work of the others.
+const CHUNKS_PER_THREAD: usize = 4;
+
+/// The smallest size of a chunk in bytes. Smaller logs are split into fewer
+/// chunks, since handing out tiny chunks costs more than it saves.
+const MIN_CHUNK_SIZE: usize = 256 * 1024;
+
+/// The state of the log at the start of a chunk, which are the timeline, the
+/// format and the start of the log the times of the events are counted from.
+type State = (Timeline, LogFormat, Option<NaiveDateTime>);
+
+/// The events of one chunk, with everything counted from its start.
+struct ParsedChunk {
+    events: Vec<Event>,
+    report: ParseReport,
+    /// The parser after the last line, holding the units of the events.
+    parser: EventParser,
+    lines:  usize
+}
+
+/// Split the log into chunks of about the given size in bytes. Every chunk
+/// except for the last one ends with a line ending.
+fn split(log: &[u8], size: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = log;
+    while rest.len() > size {
+        match rest[size..].iter().position(|&b| b == b'\n') {
+            Some(end) => {
+                let (chunk, tail) = rest.split_at(size + end + 1);
+                chunks.push(chunk);
+                rest = tail;
+            }
+            None => break
+        }
+    }
+    if !rest.is_empty() {
+        chunks.push(rest);
+    }
+
+    chunks
+}
+
+fn parse_chunk(chunk: &[u8], parser: EventParser) -> ParsedChunk {
+    let mut reader = EventReader::with_parser(chunk, parser);
+    let mut events = Vec::new();
+    let mut report = ParseReport::new();
+    while let Some(event) = reader.next() {
+        // Reading from memory cannot fail, so every error is about the line.
+        match event {
+            Ok(event) => {
+                if let Some(err) = reader.recovered() {
+                    report.reject(reader.line_number(), reader.line(), err.clone());
+                }
+                events.push(event);
+            }
+            Err(err) => report.reject(reader.line_number(), reader.line(), err)
+        }
+    }
+
+    ParsedChunk {
+        events,
+        report,
+        lines: reader.line_number(),
+        parser: reader.into_parser()
+    }
+}
+
+/// The state the chunks after the first are parsed in, which is the state
+/// after the first event of the log.
+fn guess_state(first_chunk: &[u8], options: &ParseOptions) -> State {
+    let mut reader = EventReader::with_options(first_chunk, options);
+    reader.find(Result::is_ok);
+    let parser = reader.into_parser();
+    let year = parser.timeline().year();
+    (
+        Timeline::new(&ParseOptions::new().year(year)),
+        parser.format(),
+        parser.units().start()
+    )
+}
+
+/// Check if a chunk that has been parsed in the guessed state has been parsed
+/// exactly as if it had been parsed after the chunks before it, which have
+/// left the log in the given state.
+fn is_valid(chunk: &ParsedChunk, state: &State, guess: &State) -> bool {
+    let (timeline, format, start) = state;
+    let (guess_timeline, guess_format, guess_start) = guess;
+    if format != guess_format || timeline.year() != guess_timeline.year() || start != guess_start {
+        return false;
+    }
+
+    // The guessed timeline has no previous event, so the first event must not
+    // change the year when it follows the previous one either. Afterwards,
+    // both timelines are the same.
+    match chunk.events.first() {
+        Some(first) => {
+            let time = first.time(chunk.parser.units());
+            let mut timeline = timeline.clone();
+            timeline.place(time) == Some(time) && timeline.year() == guess_timeline.year()
+        }
+        None => true
+    }
+}
+
+/// Parse the log on the thread pool, producing the same result as
+/// `parser::parse_reader`.
+pub(crate) fn parse(
+    log: &[u8],
+    options: &ParseOptions
+) -> Result<(Vec<Event>, UnitTable, ParseReport), ParseError> {
+    let chunks = rayon::current_num_threads() * CHUNKS_PER_THREAD;
+    parse_chunks(log, options, (log.len() / chunks).max(MIN_CHUNK_SIZE))
+}
+
+/// Parse the log in chunks of about the given size in bytes.
+pub(crate) fn parse_chunks(
+    log: &[u8],
+    options: &ParseOptions,
+    size: usize
+) -> Result<(Vec<Event>, UnitTable, ParseReport), ParseError> {
+    let chunks = split(log, size);
+    let guess = guess_state(chunks.first().copied().unwrap_or_default(), options);
+    let parsed: Vec<ParsedChunk> = chunks
+        .par_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let parser = match i {
+                0 => EventParser::new(options),
+                _ => EventParser::resume(guess.clone(), options.parse_mode())
+            };
+            parse_chunk(chunk, parser)
+        })
+        .collect();
+
+    let mut events = Vec::with_capacity(parsed.iter().map(|chunk| chunk.events.len()).sum());
+    let mut units = UnitTable::new();
+    let mut names = Names::default();
+    let mut report = ParseReport::new();
+    let mut state = (Timeline::new(options), LogFormat::Legacy, None);
+    let mut line = 0;
+    let mut offset = 0;
+    for (i, (chunk, bytes)) in parsed.into_iter().zip(&chunks).enumerate() {
+        let mut chunk = if i == 0 || is_valid(&chunk, &state, &guess) {
+            chunk
+        }
+        else {
+            debug!("Parsing chunk starting at line {} again", line + 1);
+            parse_chunk(
+                bytes,
+                EventParser::resume(state.clone(), options.parse_mode())
+            )
+        };
+
+        if let Some(rejected) = chunk.report.rejected().first() {
+            if options.parse_mode() == ParseMode::Strict {
+                return Err(rejected.error().clone().at_line(line + rejected.line()));
+            }
+        }
+
+        if !chunk.events.is_empty() {
+            state.0 = chunk.parser.timeline().clone();
+        }
+        state.1 = chunk.parser.format();
+        // The times of the events are counted from the same start in all
+        // chunks, which is set by the first event of the log.
+        if let (None, Some(start)) = (state.2, chunk.parser.units().start()) {
+            units.set_start(start);
+            state.2 = Some(start);
+        }
+
+        // The units are added in the order they have been found in, so the
+        // table is the same as the one of a sequential parse.
+        let refs: Vec<UnitRef> = chunk
+            .parser
+            .units()
+            .units()
+            .iter()
+            .map(|unit| units.intern(unit.clone()))
+            .collect();
+        for mut event in mem::take(&mut chunk.events) {
+            event.map_units(|unit| refs[unit.index()]);
+            event.move_extras(chunk.parser.units(), &mut units);
+            event.share_names(&mut names);
+            if let (Some(event_line), Some(event_offset)) = (event.line(), event.offset()) {
+                event.set_position(line + event_line, offset + event_offset);
+            }
+            events.push(event);
+        }
+
+        for rejected in chunk.report.rejected() {
+            let line = line + rejected.line();
+            error!("Error parsing, line {}: {}", line, rejected.error());
+            report.reject(line, rejected.raw(), rejected.error().clone());
+        }
+
+        line += chunk.lines;
+        offset += bytes.len() as u64;
+    }
+
+    info!(
+        "Loaded {} events successfully into memory in {} chunks ({} KBytes)",
+        events.len(),
+        chunks.len(),
+        mem::size_of::<Event>() * events.len() / 1024
+    );
+    Ok((events, units, report))
+}
@@ -1,28 +1,351 @@
-use crate::Event;
+use crate::parallel;
+use crate::payload::Names;
+use crate::{Event, EventReader, LogFormat, ParseError, ParseErrorType, ParseReport, UnitTable};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime};
+use std::fs;
+use std::io::{self, BufRead};
 use std::mem;
-use std::str::FromStr;
+use std::path::Path;
 
-/// Parse a warcraft logs string or file into an event object.
-pub fn parse<S: AsRef<str>>(log: S) -> Vec<Event> {
+/// How the year of the events is determined, since the log itself does not
+/// contain it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Year {
+    /// Assume the log has been started in the current year.
+    Current,
+    /// The log has been started in the given year.
+    Fixed(i32),
+    /// The log has been written before the given time, usually the time the
+    /// file was last modified. The year is chosen, so that the first event
+    /// happens before it.
+    Before(NaiveDateTime)
+}
+
+/// What happens when a line of the log cannot be parsed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fail on the first line that cannot be parsed, with the error pointing
+    /// to its line.
+    Strict,
+    /// Skip the lines that cannot be parsed and record them in the report.
+    #[default]
+    Lenient,
+    /// Like `Lenient`, but keep the events whose time, type and units could be
+    /// read. The fields that could not be parsed are kept as they are, as
+    /// `SuffixParams::Raw` if the prefix parameters of a combat event could be
+    /// read and as `Payload::Raw` otherwise. Their lines are still recorded in
+    /// the report.
+    BestEffort
+}
+
+/// Options that control how a log is parsed.
+#[derive(Clone, Debug)]
+pub struct ParseOptions {
+    year:     Year,
+    mode:     ParseMode,
+    parallel: bool
+}
+
+impl ParseOptions {
+    pub fn new() -> ParseOptions {
+        ParseOptions {
+            year:     Year::Current,
+            mode:     ParseMode::default(),
+            parallel: false
+        }
+    }
+
+    /// Set the year in which the first event of the log happened.
+    pub fn year(mut self, year: i32) -> ParseOptions {
+        self.year = Year::Fixed(year);
+        self
+    }
+
+    /// Infer the year from the time the given file was last modified. Unless
+    /// a year has been set explicitly, this is done automatically when
+    /// reading a log from a file.
+    pub fn year_from_file<P: AsRef<Path>>(mut self, path: P) -> io::Result<ParseOptions> {
+        let modified = fs::metadata(path)?.modified()?;
+        self.year = Year::Before(DateTime::<Local>::from(modified).naive_local());
+        Ok(self)
+    }
+
+    /// Check if the year has been set explicitly or inferred already.
+    pub fn has_year(&self) -> bool { self.year != Year::Current }
+
+    /// Set what happens when a line cannot be parsed.
+    pub fn mode(mut self, mode: ParseMode) -> ParseOptions {
+        self.mode = mode;
+        self
+    }
+
+    pub fn parse_mode(&self) -> ParseMode { self.mode }
+
+    /// Parse the log on multiple threads. Logs that are not held in memory as
+    /// a whole are read into memory first, so this only pays off for large
+    /// logs. The result is the same as when parsing sequentially.
+    pub fn parallel(mut self, parallel: bool) -> ParseOptions {
+        self.parallel = parallel;
+        self
+    }
+
+    pub fn is_parallel(&self) -> bool { self.parallel }
+
+    /// The options for reading the given file, with the year inferred from it
+    /// unless it has been set.
+    pub(crate) fn for_file(self, path: &Path) -> ParseOptions {
+        if self.has_year() {
+            return self;
+        }
+
+        match self.clone().year_from_file(path) {
+            Ok(options) => options,
+            Err(err) => {
+                warn!("Could not infer the year of log file {:?}: {:?}", path, err);
+                self
+            }
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions { ParseOptions::new() }
+}
+
+/// Keeps track of the year while the events of a log are parsed in order, so
+/// that the time of the events keeps increasing even when the log spans New
+/// Year's Eve. Midnight crossings are already covered by the day in the
+/// timestamp.
+#[derive(Clone, Debug)]
+pub(crate) struct Timeline {
+    year:   i32,
+    before: Option<NaiveDateTime>,
+    last:   Option<NaiveDateTime>
+}
+
+impl Timeline {
+    pub fn new(options: &ParseOptions) -> Timeline {
+        let (year, before) = match options.year {
+            Year::Current => (Local::now().year(), None),
+            Year::Fixed(year) => (year, None),
+            Year::Before(time) => (time.year(), Some(time))
+        };
+
+        Timeline {
+            year,
+            before,
+            last: None
+        }
+    }
+
+    /// The year the next event is assumed to happen in.
+    pub fn year(&self) -> i32 { self.year }
+
+    /// Place the next event of the log on the timeline, returning its time
+    /// in the year it happened in, which is corrected in case the year has
+    /// changed since the last event. The year of the given time is ignored.
+    /// Fails for leap days that end up in a year without one.
+    pub fn place(&mut self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        match (self.last, self.before) {
+            // The first event happens after the log has been written, so it
+            // must be from the year before.
+            (None, Some(before)) if in_year(time, self.year) > before + Duration::days(1) => {
+                self.year -= 1
+            }
+            // Jumping back by more than half a year means the year has passed.
+            (Some(last), _) if in_year(time, self.year) + Duration::days(183) < last => {
+                self.year += 1
+            }
+            _ => {}
+        }
+
+        self.last = Some(in_year(time, self.year));
+        let placed = time.with_year(self.year);
+        if placed.is_none() {
+            warn!("{} does not exist in the year {}", time, self.year);
+        }
+        placed
+    }
+}
+
+/// The given time in another year, where leap days count as the first of March
+/// in years without them, so that they can be compared to other times.
+fn in_year(time: NaiveDateTime, year: i32) -> NaiveDateTime {
+    time.with_year(year)
+        .or_else(|| (time + Duration::days(1)).with_year(year))
+        .unwrap_or(time)
+}
+
+/// Parses the lines of a log one after another, keeping track of the state
+/// that spans multiple lines, like the year and the units.
+pub struct EventParser {
+    timeline:  Timeline,
+    /// The format of the log, which is changed by every `COMBAT_LOG_VERSION`
+    /// header.
+    format:    LogFormat,
+    /// The units of all events that have been parsed.
+    units:     UnitTable,
+    /// The names of the spells and encounters of all events, which are shared
+    /// between them.
+    names:     Names,
+    mode:      ParseMode,
+    /// The error of the last line, if it has only been parsed partly.
+    recovered: Option<ParseError>
+}
+
+impl EventParser {
+    pub fn new(options: &ParseOptions) -> EventParser {
+        EventParser {
+            timeline:  Timeline::new(options),
+            format:    LogFormat::Legacy,
+            units:     UnitTable::new(),
+            names:     Names::default(),
+            mode:      options.mode,
+            recovered: None
+        }
+    }
+
+    /// Continue parsing a log at the given point of its timeline, in the given
+    /// format and counting the times from the given start, with no units
+    /// known yet.
+    pub(crate) fn resume(
+        (timeline, format, start): (Timeline, LogFormat, Option<NaiveDateTime>),
+        mode: ParseMode
+    ) -> EventParser {
+        let mut units = UnitTable::new();
+        if let Some(start) = start {
+            units.set_start(start);
+        }
+
+        EventParser {
+            timeline,
+            format,
+            units,
+            names: Names::default(),
+            mode,
+            recovered: None
+        }
+    }
+
+    pub(crate) fn timeline(&self) -> &Timeline { &self.timeline }
+
+    /// The format of the lines that are parsed next.
+    pub fn format(&self) -> LogFormat { self.format }
+
+    /// The units the parsed events refer to.
+    pub fn units(&self) -> &UnitTable { &self.units }
+
+    pub fn into_units(self) -> UnitTable { self.units }
+
+    /// The error of the line that has been parsed last, if the event has been
+    /// kept nonetheless in `ParseMode::BestEffort`.
+    pub fn recovered(&self) -> Option<&ParseError> { self.recovered.as_ref() }
+
+    /// Start parsing another log, whose events share the units of the events
+    /// that have been parsed so far.
+    pub(crate) fn restart(&mut self, options: &ParseOptions) {
+        self.timeline = Timeline::new(options);
+        self.format = LogFormat::Legacy;
+    }
+
+    /// Parse the next line of the log, which must not contain the line ending.
+    pub fn parse_line(&mut self, line: &str) -> Result<Event, ParseError> {
+        self.recovered = None;
+        let timeline = &mut self.timeline;
+        let (mut event, recovered) = Event::parse_recovering(
+            line,
+            self.format,
+            &mut self.units,
+            self.mode == ParseMode::BestEffort,
+            |time| timeline.place(time)
+        )?;
+        self.recovered = recovered;
+        if let Some(format) = event.payload().format() {
+            info!("Reading log in format {:?}", format);
+            self.format = format;
+        }
+
+        event.share_names(&mut self.names);
+        Ok(event)
+    }
+}
+
+/// Parse a warcraft logs string or file into events and the units they refer
+/// to. Lines that could not be parsed are collected in the report.
+pub fn parse<S: AsRef<str>>(log: S) -> (Vec<Event>, UnitTable, ParseReport) {
+    // Only strict parsing fails.
+    parse_with(log, &ParseOptions::default()).expect("Unable to parse log leniently")
+}
+
+/// Parse a warcraft logs string or file into events and the units they refer
+/// to, using the given options. Only fails in `ParseMode::Strict`.
+pub fn parse_with<S: AsRef<str>>(
+    log: S,
+    options: &ParseOptions
+) -> Result<(Vec<Event>, UnitTable, ParseReport), ParseError> {
+    parse_bytes(log.as_ref().as_bytes(), options)
+}
+
+/// Parse a log that is held in memory as a whole, on multiple threads if the
+/// options ask for it.
+pub(crate) fn parse_bytes(
+    log: &[u8],
+    options: &ParseOptions
+) -> Result<(Vec<Event>, UnitTable, ParseReport), ParseError> {
+    if options.parallel {
+        parallel::parse(log, options)
+    }
+    else {
+        parse_reader(log, options)
+    }
+}
+
+/// Read and parse all events of a log from the reader. Lines that could not be
+/// parsed are collected in the report, or are an error in `ParseMode::Strict`,
+/// while failing to read is always an error.
+pub fn parse_reader<R: BufRead>(
+    mut reader: R,
+    options: &ParseOptions
+) -> Result<(Vec<Event>, UnitTable, ParseReport), ParseError> {
+    if options.parallel {
+        let mut log = Vec::new();
+        reader.read_to_end(&mut log)?;
+        return parallel::parse(&log, options);
+    }
+
+    let mut reader = EventReader::with_options(reader, options);
+    let mut report = ParseReport::new();
     let mut result = Vec::new();
-    for (e, l) in log.as_ref().lines().enumerate() {
-        // TODO: At the moment, errors are only logged. They should instead be
-        // made accessible via the API.
-        match Event::from_str(l) {
-            Ok(event) => result.push(event),
-            Err(err) => error!(
-                "Error parsing, line {}, column {}: {:?}",
-                e + 1,
-                err.col(),
-                err.typ()
-            )
+    while let Some(event) = reader.next() {
+        match event {
+            Ok(event) => {
+                if let Some(err) = reader.recovered() {
+                    warn!("Parsed line {} only partly: {}", reader.line_number(), err);
+                    report.reject(reader.line_number(), reader.line(), err.clone());
+                }
+                result.push(event);
+            }
+            Err(err) => {
+                if let ParseErrorType::IOErr(_) = err.typ() {
+                    return Err(err);
+                }
+                if options.mode == ParseMode::Strict {
+                    return Err(err.at_line(reader.line_number()));
+                }
+
+                error!("Error parsing, line {}: {}", reader.line_number(), err);
+                report.reject(reader.line_number(), reader.line(), err);
+            }
         }
     }
 
+    // The events are kept for as long as the log, so the room the vector has
+    // grown by is given back.
+    result.shrink_to_fit();
     info!(
         "Loaded {} events successfully into memory ({} KBytes)",
         result.len(),
         mem::size_of::<Event>() * result.len() / 1024
     );
-    result
+    Ok((result, reader.into_units(), report))
 }
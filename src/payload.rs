@@ -0,0 +1,908 @@
+//! The typed information that is carried by an event after the source and
+//! target units, depending on the type of the event.
+
+use crate::event::{unit_cols, EventType, Prefix, Suffix};
+use crate::format::LogFormat;
+use crate::log::{ParseError, ParseErrorType};
+use crate::tokenizer::{Field, Value};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+
+/// The spell that caused an event, or that an event refers to. Equal spells
+/// of a log are stored only once, which also keeps payloads small.
+///
+/// With the `serde` feature, a spell is serialized as an object with its `id`,
+/// `name` and `school`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct Spell(Arc<SpellData>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct SpellData {
+    id:     u32,
+    name:   Arc<str>,
+    /// The bitmask of the magic schools of the spell, e.g. `0x4` for fire.
+    school: u32
+}
+
+/// Everything that is known about a damaging event besides its spell. The
+/// amounts are kept in 32 bits, and lines with larger amounts are rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Damage {
+    pub(crate) amount:   u32,
+    pub(crate) overkill: u32,
+    pub(crate) school:   u8,
+    pub(crate) resisted: u32,
+    pub(crate) blocked:  u32,
+    pub(crate) absorbed: u32,
+    pub(crate) critical: bool,
+    pub(crate) glancing: bool,
+    pub(crate) crushing: bool
+}
+
+/// Everything that is known about a healing event besides its spell. Its
+/// amounts are kept in 32 bits like those of `Damage`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Heal {
+    pub(crate) amount:   u32,
+    pub(crate) overheal: u32,
+    pub(crate) absorbed: u32,
+    pub(crate) critical: bool
+}
+
+/// The reason an attack or spell did not hit its target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "SCREAMING_SNAKE_CASE")
+)]
+pub enum MissType {
+    Absorb,
+    Block,
+    Deflect,
+    Dodge,
+    Evade,
+    Immune,
+    Miss,
+    Parry,
+    Reflect,
+    Resist
+}
+
+/// An attack or spell that did not hit its target. Partial misses, like
+/// absorbs, also carry the amount that has been missed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Miss {
+    typ:    MissType,
+    amount: Option<u32>
+}
+
+/// Whether an aura is beneficial or harmful to the unit it has been applied
+/// to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "SCREAMING_SNAKE_CASE")
+)]
+pub enum AuraType {
+    Buff,
+    Debuff
+}
+
+/// The kind of environment that caused environmental damage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "SCREAMING_SNAKE_CASE")
+)]
+pub enum EnvironmentalType {
+    Drowning,
+    Falling,
+    Fatigue,
+    Fire,
+    Lava,
+    Slime
+}
+
+/// The parameters of a combat event that are determined by its prefix.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum PrefixParams {
+    /// Melee swings have no prefix parameters.
+    None,
+    Spell(Spell),
+    Environmental(EnvironmentalType)
+}
+
+/// The parameters of a combat event that are determined by its suffix.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum SuffixParams {
+    /// The suffix has no parameters, like `Suffix::CastSuccess`.
+    None,
+    Damage(Damage),
+    Missed(Miss),
+    Heal(Heal),
+    /// The target gained the amount of the given power type, e.g. mana.
+    Energize {
+        amount:     u32,
+        power_type: i32
+    },
+    /// The amount of the power type has been drained or leeched from the
+    /// target, of which the source gained the extra amount.
+    Drain {
+        amount: u32,
+        power_type: i32,
+        extra_amount: u32
+    },
+    /// The extra spell that has been interrupted or that could not be
+    /// dispelled.
+    ExtraSpell {
+        extra_spell: Spell
+    },
+    /// The extra spell has been dispelled or stolen from the target, or the
+    /// aura has been broken by it.
+    Dispel {
+        extra_spell: Spell,
+        typ: AuraType
+    },
+    ExtraAttacks {
+        amount: u32
+    },
+    /// An aura has been applied, refreshed, removed or broken. Dose events
+    /// additionally contain the number of stacks.
+    Aura {
+        typ:    AuraType,
+        stacks: Option<u32>
+    },
+    /// The cast of a spell has failed for the given reason.
+    CastFailed {
+        reason: Arc<str>
+    },
+    /// The parameters of a suffix that could not be parsed in
+    /// `ParseMode::BestEffort`, exactly as they are written in the log.
+    Raw(Box<[String]>)
+}
+
+/// The typed payload of an event, containing all the information that follows
+/// the source and target units in a log line.
+///
+/// With the `serde` feature, payloads are serialized externally tagged with the
+/// names of the variants in snake case, like `"empty"` or
+/// `{"combat": {"prefix": {"spell": {...}}, "suffix": {"damage": {...}}}}`.
+/// Miss, aura and environmental types are written like they are in the log.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Payload {
+    /// The event carries no further information, like `EventType::UnitDied`.
+    Empty,
+    /// The parameters of a combat event, in the order they are found in the
+    /// log.
+    Combat {
+        prefix: PrefixParams,
+        suffix: SuffixParams
+    },
+    /// A temporary enchantment has been applied to or removed from the item
+    /// of the target.
+    Enchant(Box<Enchant>),
+    /// The header of a versioned log, announcing its format.
+    Version(Box<Version>),
+    /// A boss encounter has been started.
+    EncounterStart(Box<EncounterStart>),
+    /// A boss encounter has ended, either with a kill or a wipe.
+    EncounterEnd(Box<EncounterEnd>),
+    /// An event of an unknown type, with its fields exactly as they follow
+    /// the event type in the log.
+    Unknown(Box<Unknown>),
+    /// The fields of an event whose payload could not be parsed in
+    /// `ParseMode::BestEffort`, exactly as they follow the units in the log,
+    /// or the event type for events that are not about units.
+    Raw(Box<[String]>)
+}
+
+/// The temporary enchantment of an `EventType::EnchantApplied` or
+/// `EventType::EnchantRemoved` event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Enchant {
+    pub(crate) name:      Arc<str>,
+    pub(crate) item_id:   u32,
+    pub(crate) item_name: Arc<str>
+}
+
+/// The header of a versioned log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Version {
+    pub(crate) format:  LogFormat,
+    pub(crate) build:   Option<Arc<str>>,
+    pub(crate) project: Option<u32>
+}
+
+/// The start of a boss encounter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EncounterStart {
+    pub(crate) id: u32,
+    pub(crate) name: Arc<str>,
+    pub(crate) difficulty: u32,
+    pub(crate) group_size: u32,
+    pub(crate) instance: Option<u32>
+}
+
+/// The end of a boss encounter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EncounterEnd {
+    pub(crate) id: u32,
+    pub(crate) name: Arc<str>,
+    pub(crate) difficulty: u32,
+    pub(crate) group_size: u32,
+    pub(crate) success: bool,
+    /// The duration of the encounter in milliseconds.
+    pub(crate) fight_time: Option<u32>
+}
+
+/// An event of a type this crate does not know about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Unknown {
+    /// The name of the event type, which is shared like the names of spells.
+    pub(crate) name:   Arc<str>,
+    pub(crate) fields: Box<[String]>
+}
+
+/// Shares equal names and spells between payloads, so that a spell that is
+/// found in thousands of events is only stored once.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Names {
+    names:  HashSet<Arc<str>>,
+    spells: HashSet<Spell>
+}
+
+impl Names {
+    /// Replace the name by the equal one that is already shared.
+    fn share(&mut self, name: &mut Arc<str>) {
+        match self.names.get(name) {
+            Some(shared) => *name = shared.clone(),
+            None => {
+                self.names.insert(name.clone());
+            }
+        }
+    }
+
+    /// Replace the spell by the equal one that is already shared. The names
+    /// of spells with the same name but another id or school are shared as
+    /// well.
+    fn share_spell(&mut self, spell: &mut Spell) {
+        match self.spells.get(spell) {
+            Some(shared) => *spell = shared.clone(),
+            None => {
+                self.share(&mut Arc::make_mut(&mut spell.0).name);
+                self.spells.insert(spell.clone());
+            }
+        }
+    }
+}
+
+/// The fields starting at the given column, exactly as they are written in
+/// the log.
+fn raw_fields(fields: &[Field], col: usize) -> Box<[String]> {
+    fields
+        .iter()
+        .skip(col)
+        .map(|f| f.raw().to_string())
+        .collect()
+}
+
+/// Read the decimal argument at the given column, or fail with
+/// `ParseErrorType::InvalidArg` pointing to that column.
+pub(crate) fn arg<T: TryFrom<i64>>(fields: &[Field], col: usize) -> Result<T, ParseError> {
+    fields
+        .get(col)
+        .and_then(Field::int)
+        .and_then(|int| T::try_from(int).ok())
+        .ok_or_else(|| ParseError::new(ParseErrorType::InvalidArg, col))
+}
+
+/// Read a hexadecimal argument formatted like `0x1a`.
+pub(crate) fn hex_arg(fields: &[Field], col: usize) -> Result<u32, ParseError> {
+    fields
+        .get(col)
+        .and_then(Field::hex)
+        .and_then(|hex| u32::try_from(hex).ok())
+        .ok_or_else(|| ParseError::new(ParseErrorType::InvalidArg, col))
+}
+
+/// Read a boolean argument, which is `1` when set and `nil` otherwise.
+fn flag_arg(fields: &[Field], col: usize) -> Result<bool, ParseError> {
+    match fields.get(col).map(Field::value) {
+        Some(Value::Int(1)) => Ok(true),
+        Some(Value::Nil) => Ok(false),
+        _ => Err(ParseError::new(ParseErrorType::InvalidArg, col))
+    }
+}
+
+/// Read a quoted string argument.
+fn str_arg(fields: &[Field], col: usize) -> Result<Arc<str>, ParseError> {
+    match fields.get(col).map(Field::value) {
+        Some(Value::Str(s)) => Ok(Arc::from(&**s)),
+        _ => Err(ParseError::new(ParseErrorType::InvalidArg, col))
+    }
+}
+
+/// Read the decimal argument at the given column, or `None` if the line ends
+/// before it.
+fn opt_arg<T: TryFrom<i64>>(fields: &[Field], col: usize) -> Result<Option<T>, ParseError> {
+    if col < fields.len() {
+        arg(fields, col).map(Some)
+    }
+    else {
+        Ok(None)
+    }
+}
+
+/// The field following the given key in lines like `KEY,value,OTHER,value`.
+fn value_of<'a>(fields: &[Field<'a>], key: &str) -> Option<&'a str> {
+    let col = fields.iter().position(|f| f.raw() == key)?;
+    fields.get(col + 1).map(Field::raw)
+}
+
+/// The unquoted word at the given column, like `BUFF`, or an empty string if
+/// the column does not exist.
+fn word_arg<'a>(fields: &[Field<'a>], col: usize) -> &'a str {
+    fields.get(col).map_or("", Field::raw)
+}
+
+/// The columns the prefix and the suffix parameters of a combat event start
+/// at. The advanced fields of versioned logs are written in front of the
+/// suffix parameters, and for environmental damage even in front of the
+/// prefix parameters.
+pub(crate) fn param_cols(prefix: Prefix, suffix: Suffix, format: LogFormat) -> (usize, usize) {
+    let col = format.payload_col();
+    let advanced = format.advanced_fields(suffix);
+    match prefix {
+        Prefix::Environmental => (col + advanced, col + advanced + 1),
+        _ => (col, col + prefix.num_params() + advanced)
+    }
+}
+
+impl Spell {
+    pub fn new(id: u32, name: Arc<str>, school: u32) -> Spell {
+        Spell(Arc::new(SpellData { id, name, school }))
+    }
+
+    /// Parse the spell id, name and school starting at the given column.
+    pub fn parse(fields: &[Field], col: usize) -> Result<Spell, ParseError> {
+        Ok(Spell::new(
+            arg(fields, col)?,
+            str_arg(fields, col + 1)?,
+            hex_arg(fields, col + 2)?
+        ))
+    }
+
+    pub fn id(&self) -> u32 { self.0.id }
+
+    pub fn name(&self) -> &str { &self.0.name }
+
+    pub fn school(&self) -> u32 { self.0.school }
+}
+
+impl Damage {
+    /// Parse the nine damage columns starting at the given column. Logs with a
+    /// base amount have it following the amount, which is skipped.
+    pub fn parse(fields: &[Field], col: usize, format: LogFormat) -> Result<Damage, ParseError> {
+        let amount = arg(fields, col)?;
+        let col = col + format.base_amount() as usize;
+        // Versioned logs write an overkill of -1 when there is none.
+        let overkill: i64 = arg(fields, col + 1)?;
+
+        Ok(Damage {
+            amount,
+            overkill: u32::try_from(overkill.max(0))
+                .map_err(|_| ParseError::new(ParseErrorType::InvalidArg, col + 1))?,
+            school: arg(fields, col + 2)?,
+            resisted: arg(fields, col + 3)?,
+            blocked: arg(fields, col + 4)?,
+            absorbed: arg(fields, col + 5)?,
+            critical: flag_arg(fields, col + 6)?,
+            glancing: flag_arg(fields, col + 7)?,
+            crushing: flag_arg(fields, col + 8)?
+        })
+    }
+
+    /// The damage that has actually been dealt, including the overkill.
+    pub fn amount(&self) -> u64 { self.amount.into() }
+
+    /// The part of the amount that exceeded the remaining health of the
+    /// target.
+    pub fn overkill(&self) -> u64 { self.overkill.into() }
+
+    pub fn school(&self) -> u32 { self.school.into() }
+
+    pub fn resisted(&self) -> u64 { self.resisted.into() }
+
+    pub fn blocked(&self) -> u64 { self.blocked.into() }
+
+    pub fn absorbed(&self) -> u64 { self.absorbed.into() }
+
+    pub fn critical(&self) -> bool { self.critical }
+
+    pub fn glancing(&self) -> bool { self.glancing }
+
+    pub fn crushing(&self) -> bool { self.crushing }
+}
+
+impl Heal {
+    /// Parse the four healing columns starting at the given column. Logs with
+    /// a base amount have it following the amount, which is skipped.
+    pub fn parse(fields: &[Field], col: usize, format: LogFormat) -> Result<Heal, ParseError> {
+        let amount = arg(fields, col)?;
+        let col = col + format.base_amount() as usize;
+
+        Ok(Heal {
+            amount,
+            overheal: arg(fields, col + 1)?,
+            absorbed: arg(fields, col + 2)?,
+            critical: flag_arg(fields, col + 3)?
+        })
+    }
+
+    /// The healing that has been done, including the overheal.
+    pub fn amount(&self) -> u64 { self.amount.into() }
+
+    /// The part of the amount that exceeded the missing health of the target.
+    pub fn overheal(&self) -> u64 { self.overheal.into() }
+
+    pub fn absorbed(&self) -> u64 { self.absorbed.into() }
+
+    pub fn critical(&self) -> bool { self.critical }
+}
+
+impl MissType {
+    pub fn from_str<S: AsRef<str>>(s: S, col: usize) -> Result<MissType, ParseError> {
+        match s.as_ref() {
+            "ABSORB" => Ok(MissType::Absorb),
+            "BLOCK" => Ok(MissType::Block),
+            "DEFLECT" => Ok(MissType::Deflect),
+            "DODGE" => Ok(MissType::Dodge),
+            "EVADE" => Ok(MissType::Evade),
+            "IMMUNE" => Ok(MissType::Immune),
+            "MISS" => Ok(MissType::Miss),
+            "PARRY" => Ok(MissType::Parry),
+            "REFLECT" => Ok(MissType::Reflect),
+            "RESIST" => Ok(MissType::Resist),
+            _ => Err(ParseError::new(ParseErrorType::InvalidArg, col))
+        }
+    }
+}
+
+/// Writes the miss type as it is found in the log, like `DODGE`.
+impl fmt::Display for MissType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            MissType::Absorb => "ABSORB",
+            MissType::Block => "BLOCK",
+            MissType::Deflect => "DEFLECT",
+            MissType::Dodge => "DODGE",
+            MissType::Evade => "EVADE",
+            MissType::Immune => "IMMUNE",
+            MissType::Miss => "MISS",
+            MissType::Parry => "PARRY",
+            MissType::Reflect => "REFLECT",
+            MissType::Resist => "RESIST"
+        })
+    }
+}
+
+impl Miss {
+    pub fn new(typ: MissType, amount: Option<u32>) -> Miss { Miss { typ, amount } }
+
+    /// Parse the miss type and, if present, the missed amount starting at the
+    /// given column. Versioned logs have a flag for off hand attacks in
+    /// between.
+    pub fn parse(fields: &[Field], col: usize, format: LogFormat) -> Result<Miss, ParseError> {
+        let typ = MissType::from_str(word_arg(fields, col), col)?;
+        let amount = match format {
+            LogFormat::Legacy => opt_arg(fields, col + 1)?,
+            LogFormat::Versioned { .. } => opt_arg(fields, col + 2)?
+        };
+
+        Ok(Miss { typ, amount })
+    }
+
+    pub fn typ(&self) -> MissType { self.typ }
+
+    /// The amount that has been missed, for instance the absorbed damage.
+    pub fn amount(&self) -> Option<u64> { self.amount.map(u64::from) }
+}
+
+impl Enchant {
+    /// The name of the enchantment, like `Instant Poison IX`.
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn item_id(&self) -> u32 { self.item_id }
+
+    pub fn item_name(&self) -> &str { &self.item_name }
+}
+
+impl Version {
+    pub fn format(&self) -> LogFormat { self.format }
+
+    /// The version of the client, like `9.2.7`.
+    pub fn build(&self) -> Option<&str> { self.build.as_deref() }
+
+    pub fn project(&self) -> Option<u32> { self.project }
+}
+
+impl EncounterStart {
+    pub fn id(&self) -> u32 { self.id }
+
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn difficulty(&self) -> u32 { self.difficulty }
+
+    pub fn group_size(&self) -> u32 { self.group_size }
+
+    pub fn instance(&self) -> Option<u32> { self.instance }
+}
+
+impl EncounterEnd {
+    pub fn id(&self) -> u32 { self.id }
+
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn difficulty(&self) -> u32 { self.difficulty }
+
+    pub fn group_size(&self) -> u32 { self.group_size }
+
+    /// Whether the boss has been killed.
+    pub fn success(&self) -> bool { self.success }
+
+    /// The duration of the encounter in milliseconds, which older logs do not
+    /// have.
+    pub fn fight_time(&self) -> Option<u32> { self.fight_time }
+}
+
+impl Unknown {
+    /// The name of the event type as it is written in the log.
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn fields(&self) -> &[String] { &self.fields }
+}
+
+impl AuraType {
+    pub fn from_str<S: AsRef<str>>(s: S, col: usize) -> Result<AuraType, ParseError> {
+        match s.as_ref() {
+            "BUFF" => Ok(AuraType::Buff),
+            "DEBUFF" => Ok(AuraType::Debuff),
+            _ => Err(ParseError::new(ParseErrorType::InvalidArg, col))
+        }
+    }
+}
+
+impl fmt::Display for AuraType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AuraType::Buff => "BUFF",
+            AuraType::Debuff => "DEBUFF"
+        })
+    }
+}
+
+impl EnvironmentalType {
+    pub fn from_str<S: AsRef<str>>(s: S, col: usize) -> Result<EnvironmentalType, ParseError> {
+        match s.as_ref() {
+            "DROWNING" => Ok(EnvironmentalType::Drowning),
+            "FALLING" => Ok(EnvironmentalType::Falling),
+            "FATIGUE" => Ok(EnvironmentalType::Fatigue),
+            "FIRE" => Ok(EnvironmentalType::Fire),
+            "LAVA" => Ok(EnvironmentalType::Lava),
+            "SLIME" => Ok(EnvironmentalType::Slime),
+            _ => Err(ParseError::new(ParseErrorType::InvalidArg, col))
+        }
+    }
+}
+
+impl fmt::Display for EnvironmentalType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            EnvironmentalType::Drowning => "DROWNING",
+            EnvironmentalType::Falling => "FALLING",
+            EnvironmentalType::Fatigue => "FATIGUE",
+            EnvironmentalType::Fire => "FIRE",
+            EnvironmentalType::Lava => "LAVA",
+            EnvironmentalType::Slime => "SLIME"
+        })
+    }
+}
+
+impl PrefixParams {
+    /// Parse the parameters of the prefix starting at the given column.
+    pub fn parse(prefix: Prefix, fields: &[Field], col: usize) -> Result<PrefixParams, ParseError> {
+        Ok(match prefix {
+            Prefix::Swing => PrefixParams::None,
+            Prefix::Environmental => PrefixParams::Environmental(EnvironmentalType::from_str(
+                word_arg(fields, col),
+                col
+            )?),
+            Prefix::Range
+            | Prefix::Spell
+            | Prefix::SpellPeriodic
+            | Prefix::SpellBuilding
+            | Prefix::DamageShield
+            | Prefix::DamageSplit => PrefixParams::Spell(Spell::parse(fields, col)?)
+        })
+    }
+}
+
+impl SuffixParams {
+    /// Parse the parameters of the suffix starting at the given column.
+    pub fn parse(
+        suffix: Suffix,
+        fields: &[Field],
+        col: usize,
+        format: LogFormat
+    ) -> Result<SuffixParams, ParseError> {
+        let aura_type = |col| AuraType::from_str(word_arg(fields, col), col);
+        // Extra spells of legacy logs write their school in decimal instead of
+        // hexadecimal.
+        let extra_spell = || -> Result<Spell, ParseError> {
+            Ok(Spell::new(
+                arg(fields, col)?,
+                str_arg(fields, col + 1)?,
+                arg(fields, col + 2).or_else(|_| hex_arg(fields, col + 2))?
+            ))
+        };
+
+        Ok(match suffix {
+            Suffix::Damage => SuffixParams::Damage(Damage::parse(fields, col, format)?),
+            Suffix::Missed => SuffixParams::Missed(Miss::parse(fields, col, format)?),
+            Suffix::Heal => SuffixParams::Heal(Heal::parse(fields, col, format)?),
+            // Versioned logs have the power that has been wasted in between.
+            Suffix::Energize => SuffixParams::Energize {
+                amount:     arg(fields, col)?,
+                power_type: match format {
+                    LogFormat::Legacy => arg(fields, col + 1)?,
+                    LogFormat::Versioned { .. } => arg(fields, col + 2)?
+                }
+            },
+            Suffix::Drain | Suffix::Leech => SuffixParams::Drain {
+                amount: arg(fields, col)?,
+                power_type: arg(fields, col + 1)?,
+                extra_amount: arg(fields, col + 2)?
+            },
+            Suffix::Interrupt | Suffix::DispelFailed => SuffixParams::ExtraSpell {
+                extra_spell: extra_spell()?
+            },
+            Suffix::Dispel | Suffix::Stolen | Suffix::AuraBrokenSpell => SuffixParams::Dispel {
+                extra_spell: extra_spell()?,
+                typ: aura_type(col + 3)?
+            },
+            Suffix::ExtraAttacks => SuffixParams::ExtraAttacks {
+                amount: arg(fields, col)?
+            },
+            Suffix::AuraApplied
+            | Suffix::AuraRemoved
+            | Suffix::AuraRefresh
+            | Suffix::AuraBroken => SuffixParams::Aura {
+                typ:    aura_type(col)?,
+                stacks: None
+            },
+            Suffix::AuraAppliedDose | Suffix::AuraRemovedDose => SuffixParams::Aura {
+                typ:    aura_type(col)?,
+                stacks: Some(arg(fields, col + 1)?)
+            },
+            Suffix::CastFailed => SuffixParams::CastFailed {
+                reason: str_arg(fields, col)?
+            },
+            Suffix::CastStart
+            | Suffix::CastSuccess
+            | Suffix::Instakill
+            | Suffix::DurabilityDamage
+            | Suffix::DurabilityDamageAll
+            | Suffix::Create
+            | Suffix::Summon
+            | Suffix::Resurrect => SuffixParams::None
+        })
+    }
+}
+
+impl Payload {
+    /// Parse the payload like `parse`, but keep the fields that could not be
+    /// parsed as they are, returning the error alongside. Combat events keep
+    /// their prefix parameters if only their suffix parameters are malformed.
+    pub(crate) fn parse_recovering(
+        typ: &EventType,
+        fields: &[Field],
+        format: LogFormat
+    ) -> (Payload, Option<ParseError>) {
+        let err = match Payload::parse(typ, fields, format) {
+            Ok(payload) => return (payload, None),
+            Err(err) => err
+        };
+
+        let col = unit_cols(typ, format).map_or(1, |_| format.payload_col());
+        let payload = match *typ {
+            EventType::Combat(prefix, suffix) if fields.len() >= col => {
+                let (prefix_col, suffix_col) = param_cols(prefix, suffix, format);
+                match PrefixParams::parse(prefix, fields, prefix_col) {
+                    Ok(prefix) => Payload::Combat {
+                        prefix,
+                        suffix: SuffixParams::Raw(raw_fields(fields, suffix_col))
+                    },
+                    Err(_) => Payload::Raw(raw_fields(fields, col))
+                }
+            }
+            _ => Payload::Raw(raw_fields(fields, col))
+        };
+        (payload, Some(err))
+    }
+
+    /// Parse the payload of an event of the given type. The fields are all the
+    /// comma separated fields of the event line, where the payload of most
+    /// events starts after the target unit.
+    pub fn parse(
+        typ: &EventType,
+        fields: &[Field],
+        format: LogFormat
+    ) -> Result<Payload, ParseError> {
+        let col = format.payload_col();
+
+        Ok(match *typ {
+            EventType::Unknown(ref name) => Payload::Unknown(Box::new(Unknown {
+                name:   name.clone(),
+                fields: raw_fields(fields, 1)
+            })),
+            // These events are not about units, so their payload follows the
+            // event type.
+            EventType::CombatLogVersion => Payload::Version(Box::new(Version {
+                format:  LogFormat::from_header(fields)?,
+                build:   value_of(fields, "BUILD_VERSION").map(Arc::from),
+                project: value_of(fields, "PROJECT_ID").and_then(|id| id.parse().ok())
+            })),
+            EventType::EncounterStart => Payload::EncounterStart(Box::new(EncounterStart {
+                id: arg(fields, 1)?,
+                name: str_arg(fields, 2)?,
+                difficulty: arg(fields, 3)?,
+                group_size: arg(fields, 4)?,
+                instance: opt_arg(fields, 5)?
+            })),
+            EventType::EncounterEnd => Payload::EncounterEnd(Box::new(EncounterEnd {
+                id: arg(fields, 1)?,
+                name: str_arg(fields, 2)?,
+                difficulty: arg(fields, 3)?,
+                group_size: arg(fields, 4)?,
+                success: arg::<u8>(fields, 5)? == 1,
+                fight_time: opt_arg(fields, 6)?
+            })),
+            // Every other known event has both units, even if they are nil.
+            _ if fields.len() < col => {
+                return Err(ParseError::new(ParseErrorType::InvalidArg, fields.len()))
+            }
+            EventType::Combat(prefix, suffix) => {
+                let (prefix_col, suffix_col) = param_cols(prefix, suffix, format);
+                Payload::Combat {
+                    prefix: PrefixParams::parse(prefix, fields, prefix_col)?,
+                    suffix: SuffixParams::parse(suffix, fields, suffix_col, format)?
+                }
+            }
+            EventType::EnchantApplied | EventType::EnchantRemoved => {
+                Payload::Enchant(Box::new(Enchant {
+                    name:      str_arg(fields, col)?,
+                    item_id:   arg(fields, col + 1)?,
+                    item_name: str_arg(fields, col + 2)?
+                }))
+            }
+            EventType::PartyKill | EventType::UnitDied | EventType::UnitDestroyed => Payload::Empty
+        })
+    }
+
+    /// Replace all names of the payload by the equal names that are already
+    /// shared.
+    pub(crate) fn share_names(&mut self, names: &mut Names) {
+        match self {
+            Payload::Combat { prefix, suffix } => {
+                if let PrefixParams::Spell(spell) = prefix {
+                    names.share_spell(spell);
+                }
+                match suffix {
+                    SuffixParams::ExtraSpell { extra_spell }
+                    | SuffixParams::Dispel { extra_spell, .. } => names.share_spell(extra_spell),
+                    SuffixParams::CastFailed { reason } => names.share(reason),
+                    _ => {}
+                }
+            }
+            Payload::Enchant(enchant) => {
+                names.share(&mut enchant.name);
+                names.share(&mut enchant.item_name);
+            }
+            Payload::EncounterStart(start) => names.share(&mut start.name),
+            Payload::EncounterEnd(end) => names.share(&mut end.name),
+            Payload::Unknown(unknown) => names.share(&mut unknown.name),
+            _ => {}
+        }
+    }
+
+    /// The format announced by a `COMBAT_LOG_VERSION` event.
+    pub fn format(&self) -> Option<LogFormat> {
+        match self {
+            Payload::Version(version) => Some(version.format),
+            _ => None
+        }
+    }
+
+    /// The fields of an event of unknown type, or of an event whose fields
+    /// could not be parsed.
+    pub fn raw(&self) -> Option<&[String]> {
+        match self {
+            Payload::Unknown(unknown) => Some(&unknown.fields),
+            Payload::Raw(fields) => Some(fields),
+            _ => None
+        }
+    }
+
+    /// The spell that caused the event, if any.
+    pub fn spell(&self) -> Option<&Spell> {
+        match self {
+            Payload::Combat {
+                prefix: PrefixParams::Spell(spell),
+                ..
+            } => Some(spell),
+            _ => None
+        }
+    }
+
+    /// The damage information, if this is the payload of a damaging event.
+    pub fn damage(&self) -> Option<&Damage> {
+        match self {
+            Payload::Combat {
+                suffix: SuffixParams::Damage(damage),
+                ..
+            } => Some(damage),
+            _ => None
+        }
+    }
+
+    /// The healing information, if this is the payload of a healing event.
+    pub fn heal(&self) -> Option<&Heal> {
+        match self {
+            Payload::Combat {
+                suffix: SuffixParams::Heal(heal),
+                ..
+            } => Some(heal),
+            _ => None
+        }
+    }
+
+    /// The amount of damage or healing, or `None` if the payload is of
+    /// neither.
+    pub fn amount(&self) -> Option<u64> {
+        self.damage()
+            .map(Damage::amount)
+            .or_else(|| self.heal().map(Heal::amount))
+    }
+}
@@ -0,0 +1,165 @@
+use crate::log::ParseError;
+use crate::parser::{EventParser, ParseOptions};
+use crate::unit::Unit;
+use crate::unit_table::UnitTable;
+use crate::Event;
+use std::io::BufRead;
+
+/// Reads the events of a log one line at a time, so that the log never has to
+/// be held in memory as a whole.
+pub struct EventReader<R> {
+    reader: R,
+    parser: EventParser,
+    /// Buffer for the raw bytes of the current line.
+    buf: Vec<u8>,
+    /// The current line without its line ending.
+    line: String,
+    line_number: usize,
+    /// The position of the start of the current line in bytes.
+    offset: u64,
+    /// The position of the start of the next line in bytes.
+    next_offset: u64,
+    done: bool
+}
+
+impl<R: BufRead> EventReader<R> {
+    pub fn new(reader: R) -> EventReader<R> {
+        EventReader::with_options(reader, &ParseOptions::default())
+    }
+
+    pub fn with_options(reader: R, options: &ParseOptions) -> EventReader<R> {
+        EventReader::with_parser(reader, EventParser::new(options))
+    }
+
+    /// Read the events with a parser that may already be in the middle of a
+    /// log. The lines are still counted from the start of the reader.
+    pub(crate) fn with_parser(reader: R, parser: EventParser) -> EventReader<R> {
+        EventReader {
+            reader,
+            parser,
+            buf: Vec::new(),
+            line: String::new(),
+            line_number: 0,
+            offset: 0,
+            next_offset: 0,
+            done: false
+        }
+    }
+
+    /// The number of the line that has been read last, starting at one.
+    pub fn line_number(&self) -> usize { self.line_number }
+
+    /// The position in bytes of the start of the line that has been read last.
+    pub fn offset(&self) -> u64 { self.offset }
+
+    /// The text of the line that has been read last, without the line ending.
+    pub fn line(&self) -> &str { &self.line }
+
+    /// The units the events that have been read so far refer to.
+    pub fn units(&self) -> &UnitTable { self.parser.units() }
+
+    pub fn into_units(self) -> UnitTable { self.parser.into_units() }
+
+    pub(crate) fn into_parser(self) -> EventParser { self.parser }
+
+    /// The error of the line that has been read last, if its event has been
+    /// kept nonetheless in `ParseMode::BestEffort`.
+    pub fn recovered(&self) -> Option<&ParseError> { self.parser.recovered() }
+
+    pub fn into_inner(self) -> R { self.reader }
+
+    /// Read the events together with copies of their units, so that they can
+    /// be passed on without the unit table of the reader, like to the
+    /// functions of `extract`.
+    pub fn resolved(self) -> ResolvedEvents<R> { ResolvedEvents(self) }
+}
+
+/// An event that has been read together with its units.
+#[derive(Clone, Debug)]
+pub struct ResolvedEvent {
+    event:  Event,
+    source: Option<Unit>,
+    target: Option<Unit>
+}
+
+impl ResolvedEvent {
+    pub fn event(&self) -> &Event { &self.event }
+
+    pub fn source(&self) -> Option<&Unit> { self.source.as_ref() }
+
+    pub fn target(&self) -> Option<&Unit> { self.target.as_ref() }
+
+    pub fn into_event(self) -> Event { self.event }
+}
+
+/// Reads the events of a log like `EventReader`, resolving their units as
+/// they are read.
+pub struct ResolvedEvents<R>(EventReader<R>);
+
+impl<R> ResolvedEvents<R> {
+    /// The reader of the events, like to find the line that has been read
+    /// last.
+    pub fn reader(&self) -> &EventReader<R> { &self.0 }
+
+    pub fn into_reader(self) -> EventReader<R> { self.0 }
+}
+
+impl<R: BufRead> Iterator for ResolvedEvents<R> {
+    type Item = Result<ResolvedEvent, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.0.next()?;
+        let units = self.0.units();
+        Some(event.map(|event| ResolvedEvent {
+            source: event.source(units).cloned(),
+            target: event.target(units).cloned(),
+            event
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for EventReader<R> {
+    type Item = Result<Event, ParseError>;
+
+    /// Read the next event. Empty lines are skipped. After the reader failed,
+    /// the error is returned once and the iteration ends.
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.buf.clear();
+            match self.reader.read_until(b'\n', &mut self.buf) {
+                Ok(0) => self.done = true,
+                Ok(len) => {
+                    self.line_number += 1;
+                    self.offset = self.next_offset;
+                    self.next_offset += len as u64;
+
+                    let mut end = self.buf.len();
+                    if self.buf[..end].ends_with(b"\n") {
+                        end -= 1;
+                    }
+                    if self.buf[..end].ends_with(b"\r") {
+                        end -= 1;
+                    }
+
+                    self.line.clear();
+                    self.line
+                        .push_str(&String::from_utf8_lossy(&self.buf[..end]));
+
+                    if !self.line.is_empty() {
+                        let event = self.parser.parse_line(&self.line);
+                        return Some(event.map(|mut event| {
+                            event.set_position(self.line_number, self.offset);
+                            event
+                        }));
+                    }
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err.into()));
+                }
+            }
+        }
+
+        None
+    }
+}
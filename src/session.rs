@@ -0,0 +1,77 @@
+//! Splitting a log into the sessions it has been recorded in. The client
+//! appends to the same file every time logging is enabled, so one file often
+//! contains several evenings or instances.
+
+use crate::event::{Event, EventType};
+use crate::log::Log;
+use crate::unit::Unit;
+use chrono::{Duration, NaiveDateTime};
+use std::collections::HashSet;
+
+/// The events of a log that have been recorded without a longer interruption.
+pub struct Session {
+    log:     Log,
+    players: HashSet<Unit>
+}
+
+impl Session {
+    fn new(log: &Log, events: Vec<Event>) -> Session {
+        let units = log.units();
+        let players = events
+            .iter()
+            .flat_map(|e| e.source(units).into_iter().chain(e.target(units)))
+            .filter(|unit| unit.is_player())
+            .cloned()
+            .collect();
+
+        Session {
+            log: log.sub_log(events),
+            players
+        }
+    }
+
+    /// The time of the first event of the session.
+    pub fn start(&self) -> NaiveDateTime { self.log.events()[0].time(self.log.units()) }
+
+    /// The time of the last event of the session.
+    pub fn end(&self) -> NaiveDateTime {
+        self.log.events()[self.log.events().len() - 1].time(self.log.units())
+    }
+
+    /// All players that took part in any event of the session.
+    pub fn players(&self) -> &HashSet<Unit> { &self.players }
+
+    pub fn log(&self) -> &Log { &self.log }
+
+    pub fn into_log(self) -> Log { self.log }
+}
+
+impl Log {
+    /// Split the log into sessions. A new session starts when no event has
+    /// been recorded for longer than the gap, on a new day, or when logging
+    /// has been enabled again, which versioned logs announce by a
+    /// `COMBAT_LOG_VERSION` line.
+    pub fn sessions(&self, gap: Duration) -> Vec<Session> {
+        let mut sessions = Vec::new();
+        let mut current: Vec<Event> = Vec::new();
+
+        for event in self.events() {
+            if let Some(last) = current.last() {
+                let (time, last) = (event.time(self.units()), last.time(self.units()));
+                if time - last > gap
+                    || time.date() != last.date()
+                    || event.typ() == EventType::CombatLogVersion
+                {
+                    sessions.push(Session::new(self, std::mem::take(&mut current)));
+                }
+            }
+            current.push(event.clone());
+        }
+
+        if !current.is_empty() {
+            sessions.push(Session::new(self, current));
+        }
+
+        sessions
+    }
+}
@@ -0,0 +1,157 @@
+//! Splitting the lines of a log into their comma separated fields. Names of
+//! units and spells are quoted and may contain commas themselves, so the
+//! fields cannot simply be split at every comma.
+
+use crate::log::{ParseError, ParseErrorType};
+use std::borrow::Cow;
+
+/// The value of a single field of a log line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    /// The field is `nil`, for instance the name of a missing unit.
+    Nil,
+    /// A quoted string, without the quotes and with escaped characters
+    /// resolved.
+    Str(Cow<'a, str>),
+    /// A hexadecimal number written like `0x1a`, used for ids, flags and spell
+    /// schools.
+    Hex(u64),
+    /// A decimal number, like an amount of damage.
+    Int(i64),
+    /// Any other unquoted text, like `BUFF` or `DODGE`.
+    Word(&'a str)
+}
+
+/// A field of a log line, with the text it has been read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field<'a> {
+    raw:   &'a str,
+    value: Value<'a>
+}
+
+impl<'a> Field<'a> {
+    /// Read a field that is not quoted.
+    fn unquoted(raw: &'a str) -> Field<'a> {
+        let value = if raw == "nil" {
+            Value::Nil
+        }
+        else if let Some(hex) = raw
+            .strip_prefix("0x")
+            .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+        {
+            Value::Hex(hex)
+        }
+        else if let Ok(int) = raw.parse() {
+            Value::Int(int)
+        }
+        else {
+            Value::Word(raw)
+        };
+
+        Field { raw, value }
+    }
+
+    /// The text of the field exactly as it has been written in the log,
+    /// including quotes.
+    pub fn raw(&self) -> &'a str { self.raw }
+
+    pub fn value(&self) -> &Value<'a> { &self.value }
+
+    pub fn is_nil(&self) -> bool { self.value == Value::Nil }
+
+    /// The text of the field, without quotes, or `None` if it is `nil`.
+    pub fn str(&self) -> Option<&str> {
+        match &self.value {
+            Value::Nil => None,
+            Value::Str(s) => Some(s),
+            _ => Some(self.raw)
+        }
+    }
+
+    /// The text of the field like `str`, but borrowed from the line unless
+    /// escaped characters had to be resolved.
+    pub fn text(&self) -> Option<Cow<'a, str>> {
+        match &self.value {
+            Value::Nil => None,
+            Value::Str(s) => Some(s.clone()),
+            _ => Some(Cow::Borrowed(self.raw))
+        }
+    }
+
+    /// The field as a decimal number, if it is one.
+    pub fn int(&self) -> Option<i64> {
+        match self.value {
+            Value::Int(int) => Some(int),
+            _ => None
+        }
+    }
+
+    /// The field as a hexadecimal number, if it is one.
+    pub fn hex(&self) -> Option<u64> {
+        match self.value {
+            Value::Hex(hex) => Some(hex),
+            _ => None
+        }
+    }
+}
+
+/// Split a line of the log into its fields. Commas and quotes inside of quoted
+/// strings are part of the string, if the quotes are escaped with a
+/// backslash. The first field contains the timestamp and the event type.
+pub fn tokenize(line: &str) -> Result<Vec<Field<'_>>, ParseError> {
+    let mut fields = Vec::new();
+    let mut rest = line;
+    loop {
+        let col = fields.len();
+        let (field, len) = if rest.starts_with('"') {
+            quoted(rest).ok_or_else(|| ParseError::new(ParseErrorType::InvalidArg, col))?
+        }
+        else {
+            let len = rest.find(',').unwrap_or(rest.len());
+            (Field::unquoted(&rest[..len]), len)
+        };
+        fields.push(field);
+
+        rest = &rest[len..];
+        match rest.strip_prefix(',') {
+            Some(next) => rest = next,
+            None if rest.is_empty() => return Ok(fields),
+            // Something follows the closing quote before the next comma.
+            None => return Err(ParseError::new(ParseErrorType::InvalidArg, col))
+        }
+    }
+}
+
+/// Read the quoted string at the start of the text. Returns the field and its
+/// length including the quotes, or `None` if the string is never closed.
+fn quoted(text: &str) -> Option<(Field<'_>, usize)> {
+    let mut escaped = false;
+    let mut unescaped: Option<String> = None;
+    for (i, c) in text.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+            unescaped
+                .get_or_insert_with(|| text[1..i - 1].to_string())
+                .push(c);
+        }
+        else if c == '\\' {
+            escaped = true;
+        }
+        else if c == '"' {
+            let value = match unescaped {
+                Some(s) => Cow::Owned(s),
+                None => Cow::Borrowed(&text[1..i])
+            };
+            let field = Field {
+                raw:   &text[..=i],
+                value: Value::Str(value)
+            };
+            return Some((field, i + 1));
+        }
+        else if let Some(s) = &mut unescaped {
+            s.push(c);
+        }
+    }
+
+    None
+}
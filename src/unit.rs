@@ -1,52 +1,111 @@
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+use crate::guid::Guid;
+use crate::unit_flags::{Reaction, UnitFlags, UnitType};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
+
+/// A unit taking part in an event. Two units are equal if they have the same
+/// id and name, regardless of their flags, which may change from event to
+/// event, for instance when a raid target icon is set.
+///
+/// With the `serde` feature, a unit is serialized as an object with the
+/// fields `guid` as written in the log, `name` and `flags` as a number.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Unit {
-    id:   u64,
-    name: String
+    guid:  Guid,
+    name:  String,
+    /// The flags as they were recorded in the event this unit is part of.
+    flags: UnitFlags
 }
 
 impl Unit {
-    /// Create a new Unit
-    pub fn new(id: u64, name: String) -> Unit { Unit { id, name } }
+    /// Create a new Unit without any flags
+    pub fn new(id: u64, name: String) -> Unit { Unit::with_flags(id, name, UnitFlags::default()) }
+
+    /// Create a new Unit with the flags it has been recorded with
+    pub fn with_flags(id: u64, name: String, flags: UnitFlags) -> Unit {
+        Unit::with_guid(Guid::new(id), name, flags)
+    }
+
+    /// Create a new Unit from any kind of id, like the textual ids of newer
+    /// clients
+    pub fn with_guid(guid: Guid, name: String, flags: UnitFlags) -> Unit {
+        Unit { guid, name, flags }
+    }
 
     /// Convert the raw Strings as found in a log file to a Unit, or None, in
     /// case they do not point to one
-    pub fn from_raw<S: AsRef<str>>(id: S, name: S) -> Option<Unit> {
-        // Check for non-hex-coded ids
-        if !id.as_ref().starts_with("0x") {
-            warn!("Invalid Unit id detected: {}", id.as_ref());
-            None
-        }
-        else {
-            let id = match u64::from_str_radix(id.as_ref().split_at(2).1, 16) {
-                Ok(id) => id,
-                Err(err) => {
-                    error!("Error parsing id {}", err);
-                    return None;
+    pub fn from_raw<S: AsRef<str>>(id: S, name: S, flags: S) -> Option<Unit> {
+        let guid = match Guid::parse(id.as_ref()) {
+            Some(guid) => guid,
+            None => {
+                // Missing units have an id of zero
+                if !id
+                    .as_ref()
+                    .trim_start_matches("0x")
+                    .trim_start_matches('0')
+                    .is_empty()
+                {
+                    warn!("Invalid Unit id detected: {}", id.as_ref());
                 }
-            };
-            if id == 0 || name.as_ref() == "nil" {
                 return None;
             }
+        };
+        if name.as_ref() == "nil" {
+            return None;
+        }
+
+        let flags = match UnitFlags::from_raw(flags.as_ref()) {
+            Some(flags) => flags,
+            None => {
+                error!("Error parsing unit flags {}", flags.as_ref());
+                return None;
+            }
+        };
 
-            let name = name.as_ref().trim_matches('\"').to_string();
+        let name = name.as_ref().trim_matches('\"').to_string();
 
-            Some(Unit { id, name })
-        }
+        Some(Unit::with_guid(guid, name, flags))
     }
 
     /// Check, if this Unit represents a Player, or something else. Returns true
     /// if it is a Player
-    pub fn is_player(&self) -> bool {
-        // TODO: Still don't know if this is correct. It seems, that Players are
-        // at least on the lower id spectrum, while other entities are at the
-        // higher spectrum
-        self.id <= u32::max_value() as u64
-    }
+    pub fn is_player(&self) -> bool { self.flags.unit_type() == Some(UnitType::Player) }
 
     /// Check if the Unit is hostile to the logger or not.
-    pub fn hostile(&self) -> bool { !self.is_player() }
+    pub fn hostile(&self) -> bool { self.flags.reaction() == Some(Reaction::Hostile) }
+
+    /// Check if the Unit is friendly to the logger, which is the case for the
+    /// members of their group and their pets, for instance.
+    pub fn friendly(&self) -> bool { self.flags.reaction() == Some(Reaction::Friendly) }
 
     pub fn name(&self) -> &String { &self.name }
 
-    pub fn id(&self) -> u64 { self.id }
+    pub fn guid(&self) -> Guid { self.guid.clone() }
+
+    /// The id of the creature template this unit has been spawned from, or
+    /// `None` if it has not been spawned from one, like players and pets.
+    pub fn entry(&self) -> Option<u32> { self.guid.entry() }
+
+    /// Check if both units are spawns of the same creature template, for
+    /// instance two different spawns of Dark Ranger Marrah.
+    pub fn same_template(&self, other: &Unit) -> bool {
+        self.entry().is_some() && self.entry() == other.entry()
+    }
+
+    pub fn flags(&self) -> UnitFlags { self.flags }
+}
+
+impl PartialEq for Unit {
+    fn eq(&self, other: &Unit) -> bool { self.guid == other.guid && self.name == other.name }
+}
+
+impl Eq for Unit {}
+
+impl Hash for Unit {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.guid.hash(state);
+        self.name.hash(state);
+    }
 }
@@ -0,0 +1,100 @@
+//! The flags of a unit, as they are found in the log after every unit id. They
+//! describe the relation of the unit to the player who recorded the log.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The group the unit is in, relative to the logging player.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Affiliation {
+    /// The unit is the logging player or belongs to them.
+    Mine,
+    Party,
+    Raid,
+    Outsider
+}
+
+/// The attitude of the unit towards the logging player.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reaction {
+    Friendly,
+    Neutral,
+    Hostile
+}
+
+/// Whether the unit is controlled by a player or by the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Control {
+    Player,
+    Npc
+}
+
+/// What kind of unit this is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnitType {
+    Player,
+    Npc,
+    Pet,
+    Guardian,
+    Object
+}
+
+/// The raw bitmask of unit flags with accessors for the different parts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct UnitFlags(u32);
+
+impl UnitFlags {
+    pub fn new(bits: u32) -> UnitFlags { UnitFlags(bits) }
+
+    /// Parse the flags from the hexadecimal representation in the log, like
+    /// `0x512`.
+    pub fn from_raw<S: AsRef<str>>(s: S) -> Option<UnitFlags> {
+        let s = s.as_ref();
+        if !s.starts_with("0x") {
+            return None;
+        }
+
+        u32::from_str_radix(&s[2..], 16).ok().map(UnitFlags)
+    }
+
+    pub fn bits(&self) -> u32 { self.0 }
+
+    pub fn affiliation(&self) -> Option<Affiliation> {
+        match self.0 & 0xf {
+            0x1 => Some(Affiliation::Mine),
+            0x2 => Some(Affiliation::Party),
+            0x4 => Some(Affiliation::Raid),
+            0x8 => Some(Affiliation::Outsider),
+            _ => None
+        }
+    }
+
+    pub fn reaction(&self) -> Option<Reaction> {
+        match self.0 & 0xf0 {
+            0x10 => Some(Reaction::Friendly),
+            0x20 => Some(Reaction::Neutral),
+            0x40 => Some(Reaction::Hostile),
+            _ => None
+        }
+    }
+
+    pub fn control(&self) -> Option<Control> {
+        match self.0 & 0x300 {
+            0x100 => Some(Control::Player),
+            0x200 => Some(Control::Npc),
+            _ => None
+        }
+    }
+
+    pub fn unit_type(&self) -> Option<UnitType> {
+        match self.0 & 0xfc00 {
+            0x400 => Some(UnitType::Player),
+            0x800 => Some(UnitType::Npc),
+            0x1000 => Some(UnitType::Pet),
+            0x2000 => Some(UnitType::Guardian),
+            0x4000 => Some(UnitType::Object),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! The units of a log, which are stored only once and referred to by the
+//! events. Most units take part in thousands of events, so this saves the
+//! memory and the time for copying their names over and over. The table also
+//! holds the start of the log, which the times of the events are relative to,
+//! and the columns of the lines that the events do not keep.
+
+use crate::event::Event;
+use crate::extras::{Extras, ExtrasRef};
+use crate::guid::Guid;
+use crate::unit::Unit;
+use crate::unit_flags::UnitFlags;
+use chrono::{Duration, NaiveDateTime, NaiveTime};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::num::NonZeroU32;
+use std::ops::Index;
+
+/// Refers to a unit in the `UnitTable` of the log an event belongs to. An
+/// optional reference takes no more space than the reference itself.
+///
+/// With the `serde` feature, it is serialized as the index of the unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UnitRef(NonZeroU32);
+
+/// All units of a log. Units with different flags are stored separately,
+/// since the flags describe the unit at the time of an event, so two
+/// references to the same unit may differ.
+///
+/// With the `serde` feature, the table is serialized as an object with the
+/// `start` of the log like `"2020-04-22T00:00:00"` or `null`, the list of its
+/// `units` and the list of the `extras`, which are the columns of each line
+/// that its event does not keep.
+#[derive(Clone, Debug, Default)]
+pub struct UnitTable {
+    units:  Vec<Unit>,
+    /// The units by their id and flags. Units with the same id may still have
+    /// different names, for instance before the name of a unit is known.
+    index:  HashMap<(Guid, u32), Vec<UnitRef>>,
+    /// The start of the log the times of the events are counted from in
+    /// milliseconds, or `None` if no event has been added yet. Events may be
+    /// before it as well.
+    start:  Option<NaiveDateTime>,
+    extras: Extras
+}
+
+impl UnitRef {
+    pub(crate) fn new(index: usize) -> UnitRef {
+        let index = u32::try_from(index + 1).expect("Too many units in the log");
+        UnitRef(NonZeroU32::new(index).unwrap())
+    }
+
+    /// The position of the unit in its table, starting at zero.
+    pub fn index(&self) -> usize { self.0.get() as usize - 1 }
+}
+
+impl UnitTable {
+    pub fn new() -> UnitTable { UnitTable::default() }
+
+    /// Create a table of the units in the given order, so that the reference
+    /// to each unit is its index in the list.
+    pub fn from_units(units: Vec<Unit>) -> UnitTable {
+        let mut table = UnitTable {
+            units:  Vec::with_capacity(units.len()),
+            index:  HashMap::with_capacity(units.len()),
+            start:  None,
+            extras: Extras::default()
+        };
+        for unit in units {
+            table.push(unit);
+        }
+
+        table
+    }
+
+    fn push(&mut self, unit: Unit) -> UnitRef {
+        let unit_ref = UnitRef::new(self.units.len());
+        self.index
+            .entry((unit.guid(), unit.flags().bits()))
+            .or_default()
+            .push(unit_ref);
+        self.units.push(unit);
+        unit_ref
+    }
+
+    fn lookup(&self, guid: &Guid, name: &str, flags: UnitFlags) -> Option<UnitRef> {
+        self.index
+            .get(&(guid.clone(), flags.bits()))?
+            .iter()
+            .copied()
+            .find(|unit_ref| self[*unit_ref].name() == name)
+    }
+
+    /// Add the unit to the table, unless it is already part of it with the
+    /// same flags, and return the reference to it.
+    pub fn intern(&mut self, unit: Unit) -> UnitRef {
+        match self.find(&unit) {
+            Some(unit_ref) => unit_ref,
+            None => self.push(unit)
+        }
+    }
+
+    /// Like `intern`, but the unit is only created if it is not part of the
+    /// table yet.
+    pub(crate) fn intern_parts(&mut self, guid: Guid, name: &str, flags: UnitFlags) -> UnitRef {
+        match self.lookup(&guid, name, flags) {
+            Some(unit_ref) => unit_ref,
+            None => self.push(Unit::with_guid(guid, name.to_string(), flags))
+        }
+    }
+
+    /// The unit the reference refers to, or `None` if it belongs to another
+    /// table.
+    pub fn get(&self, unit: UnitRef) -> Option<&Unit> { self.units.get(unit.index()) }
+
+    /// The reference to the unit with the same flags, if it is part of the
+    /// table.
+    pub fn find(&self, unit: &Unit) -> Option<UnitRef> {
+        self.lookup(&unit.guid(), unit.name(), unit.flags())
+    }
+
+    pub fn len(&self) -> usize { self.units.len() }
+
+    pub fn is_empty(&self) -> bool { self.units.is_empty() }
+
+    /// All units together with their references, in the order they have been
+    /// added.
+    pub fn iter(&self) -> impl Iterator<Item = (UnitRef, &Unit)> {
+        self.units
+            .iter()
+            .enumerate()
+            .map(|(i, unit)| (UnitRef::new(i), unit))
+    }
+
+    /// All units in the order they have been added.
+    pub fn units(&self) -> &Vec<Unit> { &self.units }
+
+    /// The start of the day of the first event of the log, which the times of
+    /// the events are relative to. Later events may be before it, for instance
+    /// when the clock has been set back.
+    pub fn start(&self) -> Option<NaiveDateTime> { self.start }
+
+    /// Count the times of the events from the start of the day of the given
+    /// time.
+    pub(crate) fn set_start(&mut self, time: NaiveDateTime) {
+        self.start = Some(time.date().and_time(NaiveTime::MIN));
+    }
+
+    /// The time in seconds and milliseconds since the start of the log, which
+    /// is negative for times before it, or `None` if the time is too far
+    /// away. The start is set by the first time.
+    pub(crate) fn offset(&mut self, time: NaiveDateTime) -> Option<(i32, u16)> {
+        let start = *self
+            .start
+            .get_or_insert_with(|| time.date().and_time(NaiveTime::MIN));
+        split_millis((time - start).num_milliseconds())
+    }
+
+    /// The time of the given seconds and milliseconds since the start of the
+    /// log.
+    pub(crate) fn time(&self, offset: (i32, u16)) -> NaiveDateTime {
+        self.start.unwrap_or_default() + Duration::milliseconds(millis_of(offset))
+    }
+
+    /// A table with the same start, but only the units the events refer to,
+    /// which the events are moved to together with their extra columns. The
+    /// times of the events stay the same.
+    pub(crate) fn sub_table(&self, events: &mut [Event]) -> UnitTable {
+        let mut units = UnitTable::new();
+        units.start = self.start;
+
+        let mut refs = vec![None; self.len()];
+        for event in events {
+            for unit in event.source_ref().into_iter().chain(event.target_ref()) {
+                refs[unit.index()].get_or_insert_with(|| units.intern(self[unit].clone()));
+            }
+            event.map_units(|unit| refs[unit.index()].unwrap());
+            event.move_extras(self, &mut units);
+        }
+
+        units
+    }
+
+    /// Keep the columns of a line that its event does not keep, or nothing if
+    /// there are none.
+    pub(crate) fn add_extras<'a, I>(&mut self, cols: I) -> Option<ExtrasRef>
+    where
+        I: IntoIterator<Item = &'a str>
+    {
+        self.extras.push(cols)
+    }
+
+    /// The columns of a line that its event does not keep, exactly as they
+    /// have been written in the log.
+    pub(crate) fn extras(&self, extras: ExtrasRef) -> impl Iterator<Item = &str> + '_ {
+        self.extras.get(extras)
+    }
+}
+
+/// Split milliseconds into seconds and the milliseconds on top of them, or
+/// `None` if there are too many seconds.
+pub(crate) fn split_millis(millis: i64) -> Option<(i32, u16)> {
+    let secs = i32::try_from(millis.div_euclid(1000)).ok()?;
+    Some((secs, millis.rem_euclid(1000) as u16))
+}
+
+/// The milliseconds of an offset split by `split_millis`.
+pub(crate) fn millis_of((secs, millis): (i32, u16)) -> i64 {
+    i64::from(secs) * 1000 + i64::from(millis)
+}
+
+/// Look up the unit of a reference. Panics if the reference belongs to
+/// another table.
+impl Index<UnitRef> for UnitTable {
+    type Output = Unit;
+
+    fn index(&self, unit: UnitRef) -> &Unit { &self.units[unit.index()] }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for UnitRef {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.index() as u64)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for UnitRef {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<UnitRef, D::Error> {
+        let index = u32::deserialize(deserializer)?;
+        if index == u32::MAX {
+            return Err(serde::de::Error::custom("unit index out of range"));
+        }
+        Ok(UnitRef::new(index as usize))
+    }
+}
+
+/// The serialized form of a `UnitTable`, without the index.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct UnitTableRepr<U, E> {
+    start:  Option<NaiveDateTime>,
+    units:  U,
+    #[serde(default)]
+    extras: Vec<E>
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for UnitTable {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        UnitTableRepr {
+            start:  self.start,
+            units:  &self.units,
+            extras: self.extras.all().collect()
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for UnitTable {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<UnitTable, D::Error> {
+        let repr = UnitTableRepr::<Vec<Unit>, Vec<String>>::deserialize(deserializer)?;
+        let mut table = UnitTable::from_units(repr.units);
+        table.start = repr.start;
+        for cols in &repr.extras {
+            table.add_extras(cols.iter().map(String::as_str));
+        }
+        Ok(table)
+    }
+}
@@ -0,0 +1,203 @@
+//! Checking a log for signs that it has been edited by hand, has been recorded
+//! with a jumping clock or has been cut off. None of these make a log
+//! unreadable, but they lead to wrong results when analysing it.
+
+use crate::event::{Event, EventType};
+use crate::guid::Guid;
+use crate::log::Log;
+use crate::unit_table::UnitRef;
+use chrono::Duration;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// How long after a `PARTY_KILL` the death of the unit may be logged, in
+/// milliseconds. The client logs the kill first, and the death as soon as the
+/// server confirms it.
+const KILL_TOLERANCE: i64 = 1000;
+
+/// The name the client uses for units whose name it does not know yet, most
+/// often pets. It is not taken as a different name.
+const UNKNOWN_NAME: &str = "Unknown";
+
+/// The kinds of problems `Log::validate` looks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FindingKind {
+    /// An event happened earlier than the event before it.
+    TimeGoesBack,
+    /// The same GUID appears with different names.
+    NameChanged,
+    /// A damage event carries no amount.
+    DamageWithoutAmount,
+    /// A unit died that is not part of any other event.
+    UnknownUnitDied,
+    /// A unit has been killed, but its death has not been logged.
+    KillWithoutDeath
+}
+
+/// A problem `Log::validate` has found.
+#[derive(Clone, Debug)]
+pub struct Finding {
+    kind:   FindingKind,
+    /// The lines of the events involved, in the order they appear in the log.
+    lines:  Vec<usize>,
+    detail: String
+}
+
+impl Finding {
+    fn new(kind: FindingKind, events: &[&Event], detail: String) -> Finding {
+        Finding {
+            kind,
+            lines: events.iter().filter_map(|event| event.line()).collect(),
+            detail
+        }
+    }
+
+    pub fn kind(&self) -> FindingKind { self.kind }
+
+    /// The numbers of the lines of the events involved, starting at one.
+    /// Events that have not been read from a log, for instance because they
+    /// have been merged from another one, have no line.
+    pub fn lines(&self) -> &Vec<usize> { &self.lines }
+
+    /// What exactly has been found, like the names of a unit.
+    pub fn detail(&self) -> &str { &self.detail }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            FindingKind::TimeGoesBack => write!(f, "time goes back: {}", self.detail)?,
+            FindingKind::NameChanged => write!(f, "name changed: {}", self.detail)?,
+            FindingKind::DamageWithoutAmount => {
+                write!(f, "damage without amount: {}", self.detail)?
+            }
+            FindingKind::UnknownUnitDied => write!(f, "unknown unit died: {}", self.detail)?,
+            FindingKind::KillWithoutDeath => write!(f, "kill without death: {}", self.detail)?
+        }
+
+        match self.lines.as_slice() {
+            [] => Ok(()),
+            [line] => write!(f, " (line {})", line),
+            lines => {
+                let lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+                write!(f, " (lines {})", lines.join(", "))
+            }
+        }
+    }
+}
+
+impl Log {
+    /// Check the log for timestamps going backwards, GUIDs with different
+    /// names, damage without an amount, deaths of units that never appeared
+    /// and kills without a death. The findings are ordered by the first event
+    /// involved.
+    pub fn validate(&self) -> Vec<Finding> {
+        let events = self.events();
+        let units = self.units();
+        // The findings together with the index of their first event.
+        let mut findings: Vec<(usize, Finding)> = Vec::new();
+
+        for (i, pair) in events.windows(2).enumerate() {
+            let (time, next) = (pair[0].time(units), pair[1].time(units));
+            if next < time {
+                findings.push((
+                    i,
+                    Finding::new(
+                        FindingKind::TimeGoesBack,
+                        &[&pair[0], &pair[1]],
+                        format!("{} is followed by {}", time, next)
+                    )
+                ));
+            }
+        }
+
+        // The first event of every name of a GUID. Every unit of the table is
+        // only looked at once, since all its events have the same name.
+        let mut names: HashMap<Guid, Vec<(&str, usize)>> = HashMap::new();
+        let mut checked: HashSet<UnitRef> = HashSet::new();
+        // The GUIDs of all units that appear in events other than deaths.
+        let mut seen: HashSet<Guid> = HashSet::new();
+        for (i, event) in events.iter().enumerate() {
+            let typ = event.typ();
+            for unit_ref in event.source_ref().into_iter().chain(event.target_ref()) {
+                let unit = &units[unit_ref];
+                if typ != EventType::UnitDied {
+                    seen.insert(unit.guid());
+                }
+                if !checked.insert(unit_ref) || unit.name() == UNKNOWN_NAME {
+                    continue;
+                }
+
+                let known = names.entry(unit.guid()).or_default();
+                if known.iter().any(|(name, _)| *name == unit.name()) {
+                    continue;
+                }
+                if let Some(&(name, first)) = known.first() {
+                    findings.push((
+                        first,
+                        Finding::new(
+                            FindingKind::NameChanged,
+                            &[&events[first], event],
+                            format!("{} is called {:?} and {:?}", unit.guid(), name, unit.name())
+                        )
+                    ));
+                }
+                known.push((unit.name(), i));
+            }
+
+            if typ.damaging() && event.amount().is_none() {
+                findings.push((
+                    i,
+                    Finding::new(FindingKind::DamageWithoutAmount, &[event], typ.to_string())
+                ));
+            }
+        }
+
+        for (i, event) in events.iter().enumerate() {
+            let target = match event.target(units) {
+                Some(target) => target,
+                None => continue
+            };
+
+            match event.typ() {
+                EventType::UnitDied if !seen.contains(&target.guid()) => {
+                    findings.push((
+                        i,
+                        Finding::new(
+                            FindingKind::UnknownUnitDied,
+                            &[event],
+                            format!("{} {:?}", target.guid(), target.name())
+                        )
+                    ));
+                }
+                EventType::PartyKill => {
+                    let until = event.time(units) + Duration::milliseconds(KILL_TOLERANCE);
+                    let died = events[i + 1..]
+                        .iter()
+                        .take_while(|later| later.time(units) <= until)
+                        .any(|later| {
+                            later.typ() == EventType::UnitDied
+                                && later.target(units).map(|unit| unit.guid())
+                                    == Some(target.guid())
+                        });
+                    if !died {
+                        findings.push((
+                            i,
+                            Finding::new(
+                                FindingKind::KillWithoutDeath,
+                                &[event],
+                                format!("{} {:?}", target.guid(), target.name())
+                            )
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // The sort is stable, so findings of the same event keep the order of
+        // the checks.
+        findings.sort_by_key(|(first, _)| *first);
+        findings.into_iter().map(|(_, finding)| finding).collect()
+    }
+}
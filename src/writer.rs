@@ -0,0 +1,408 @@
+//! Writing events back into the lines of a combat log. Parsing a line of a log
+//! without a header and writing the event again results in the very same
+//! line.
+//!
+//! Events following a `COMBAT_LOG_VERSION` header are written in the layout it
+//! announces. The columns their events do not keep, like the raid flags, the
+//! advanced fields and the base amounts, are kept in the unit table and
+//! written back as they are, so those lines are the very same as well. Events
+//! that have not been read from a log have their raid flags and advanced
+//! fields written as zeros and their base amount equal to the amount.
+
+use crate::event::{Event, EventType, Prefix};
+use crate::format::LogFormat;
+use crate::log::Log;
+use crate::payload::{Payload, PrefixParams, Spell, SuffixParams};
+use crate::unit::Unit;
+use crate::unit_table::UnitTable;
+use std::borrow::Borrow;
+use std::fmt::{self, Display, Write as _};
+use std::io::{self, Write};
+use std::iter;
+
+/// The line ending the client writes its logs with.
+pub const LINE_ENDING: &str = "\r\n";
+
+/// How units that are not present are written.
+const NIL_UNIT: &str = "0x0000000000000000,nil,0x80000000";
+
+/// How units that are not present are written in versioned logs, followed by
+/// their raid flags.
+const VERSIONED_NIL_UNIT: &str = "0000000000000000,nil,0x80000000";
+
+/// How the ids of the unit and its owner in the advanced fields are written.
+const NIL_GUID: &str = "0000000000000000";
+
+/// Write a quoted string, escaping the characters the tokenizer unescapes.
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            f.write_char('\\')?;
+        }
+        f.write_char(c)?;
+    }
+    f.write_char('"')
+}
+
+/// Write a boolean flag, which is `1` when set and `nil` otherwise.
+fn write_flag(f: &mut fmt::Formatter, flag: bool) -> fmt::Result {
+    f.write_str(if flag { ",1" } else { ",nil" })
+}
+
+/// The columns of the line that an event does not keep, in the order they are
+/// written.
+type Extras<'a, 'e> = &'a mut dyn Iterator<Item = &'e str>;
+
+/// Write the next column the event does not keep, or the default if it has
+/// not been read from a log.
+fn write_extra<D: Display>(f: &mut fmt::Formatter, extras: Extras, default: D) -> fmt::Result {
+    match extras.next() {
+        Some(col) => write!(f, ",{}", col),
+        None => write!(f, ",{}", default)
+    }
+}
+
+fn write_unit(
+    f: &mut fmt::Formatter,
+    unit: Option<&Unit>,
+    format: LogFormat,
+    extras: Extras
+) -> fmt::Result {
+    match (unit, format) {
+        (Some(unit), _) => {
+            write!(f, ",{},", unit.guid())?;
+            write_str(f, unit.name())?;
+            write!(f, ",0x{:x}", unit.flags().bits())?;
+            match format {
+                LogFormat::Legacy => Ok(()),
+                LogFormat::Versioned { .. } => write_extra(f, extras, "0x0")
+            }
+        }
+        (None, LogFormat::Legacy) => write!(f, ",{}", NIL_UNIT),
+        (None, LogFormat::Versioned { .. }) => {
+            write!(f, ",{}", VERSIONED_NIL_UNIT)?;
+            write_extra(f, extras, "0x80000000")
+        }
+    }
+}
+
+/// Write the given number of advanced fields, which start with the ids of the
+/// unit and its owner.
+fn write_advanced(f: &mut fmt::Formatter, fields: usize, extras: Extras) -> fmt::Result {
+    (0..fields).try_for_each(|i| write_extra(f, extras, if i < 2 { NIL_GUID } else { "0" }))
+}
+
+/// Write the id, name and school of a spell. The school of extra spells is
+/// written in decimal.
+fn write_spell(f: &mut fmt::Formatter, spell: &Spell, hex_school: bool) -> fmt::Result {
+    write!(f, ",{},", spell.id())?;
+    write_str(f, spell.name())?;
+    if hex_school {
+        write!(f, ",0x{:x}", spell.school())
+    }
+    else {
+        write!(f, ",{}", spell.school())
+    }
+}
+
+fn write_suffix(
+    f: &mut fmt::Formatter,
+    suffix: &SuffixParams,
+    format: LogFormat,
+    extras: Extras
+) -> fmt::Result {
+    let versioned = format != LogFormat::Legacy;
+    match suffix {
+        SuffixParams::None => Ok(()),
+        SuffixParams::Damage(damage) => {
+            write!(f, ",{}", damage.amount())?;
+            if format.base_amount() {
+                write_extra(f, extras, damage.amount())?;
+            }
+            // Versioned logs write an overkill of -1 when there is none.
+            if versioned {
+                match damage.overkill() {
+                    0 => write_extra(f, extras, -1)?,
+                    overkill => write_extra(f, extras, overkill)?
+                }
+            }
+            else {
+                write!(f, ",{}", damage.overkill())?;
+            }
+            write!(
+                f,
+                ",{},{},{},{}",
+                damage.school(),
+                damage.resisted(),
+                damage.blocked(),
+                damage.absorbed()
+            )?;
+            write_flag(f, damage.critical())?;
+            write_flag(f, damage.glancing())?;
+            write_flag(f, damage.crushing())
+        }
+        SuffixParams::Missed(miss) => {
+            write!(f, ",{}", miss.typ())?;
+            // Versioned logs have a flag for off hand attacks in between.
+            if versioned {
+                write_extra(f, extras, "nil")?;
+            }
+            match miss.amount() {
+                Some(amount) => write!(f, ",{}", amount),
+                None => Ok(())
+            }
+        }
+        SuffixParams::Heal(heal) => {
+            write!(f, ",{}", heal.amount())?;
+            if format.base_amount() {
+                write_extra(f, extras, heal.amount())?;
+            }
+            write!(f, ",{},{}", heal.overheal(), heal.absorbed())?;
+            write_flag(f, heal.critical())
+        }
+        // Versioned logs have the power that has been wasted in between.
+        SuffixParams::Energize { amount, power_type } if versioned => {
+            write!(f, ",{}", amount)?;
+            write_extra(f, extras, 0)?;
+            write!(f, ",{}", power_type)
+        }
+        SuffixParams::Energize { amount, power_type } => {
+            write!(f, ",{},{}", amount, power_type)
+        }
+        SuffixParams::Drain {
+            amount,
+            power_type,
+            extra_amount
+        } => write!(f, ",{},{},{}", amount, power_type, extra_amount),
+        SuffixParams::ExtraSpell { extra_spell } => write_spell(f, extra_spell, false),
+        SuffixParams::Dispel { extra_spell, typ } => {
+            write_spell(f, extra_spell, false)?;
+            write!(f, ",{}", typ)
+        }
+        SuffixParams::ExtraAttacks { amount } => write!(f, ",{}", amount),
+        SuffixParams::Aura { typ, stacks } => {
+            write!(f, ",{}", typ)?;
+            match stacks {
+                Some(stacks) => write!(f, ",{}", stacks),
+                None => Ok(())
+            }
+        }
+        SuffixParams::CastFailed { reason } => {
+            f.write_char(',')?;
+            write_str(f, reason)
+        }
+        SuffixParams::Raw(fields) => write_raw(f, fields)
+    }
+}
+
+/// Write fields that are kept exactly as they have been written in the log.
+fn write_raw(f: &mut fmt::Formatter, fields: &[String]) -> fmt::Result {
+    fields.iter().try_for_each(|field| write!(f, ",{}", field))
+}
+
+fn write_payload(
+    f: &mut fmt::Formatter,
+    typ: &EventType,
+    payload: &Payload,
+    format: LogFormat,
+    extras: Extras
+) -> fmt::Result {
+    match payload {
+        Payload::Empty => Ok(()),
+        Payload::Combat { prefix, suffix } => {
+            // The advanced fields come in front of the suffix parameters, and
+            // for environmental damage even in front of the prefix parameters.
+            let (advanced, environmental) = match *typ {
+                EventType::Combat(prefix, suffix) => (
+                    format.advanced_fields(suffix),
+                    prefix == Prefix::Environmental
+                ),
+                _ => (0, false)
+            };
+            if environmental {
+                write_advanced(f, advanced, extras)?;
+            }
+            match prefix {
+                PrefixParams::None => {}
+                PrefixParams::Spell(spell) => write_spell(f, spell, true)?,
+                PrefixParams::Environmental(typ) => write!(f, ",{}", typ)?
+            }
+            if !environmental {
+                write_advanced(f, advanced, extras)?;
+            }
+            write_suffix(f, suffix, format, extras)
+        }
+        Payload::Enchant(enchant) => {
+            f.write_char(',')?;
+            write_str(f, enchant.name())?;
+            write!(f, ",{},", enchant.item_id())?;
+            write_str(f, enchant.item_name())
+        }
+        Payload::Version(version) => {
+            write!(
+                f,
+                ",{},ADVANCED_LOG_ENABLED,{}",
+                version.format().version().unwrap_or_default(),
+                version.format().advanced() as u8
+            )?;
+            if let Some(build) = version.build() {
+                write!(f, ",BUILD_VERSION,{}", build)?;
+            }
+            match version.project() {
+                Some(project) => write!(f, ",PROJECT_ID,{}", project),
+                None => Ok(())
+            }
+        }
+        Payload::EncounterStart(start) => {
+            write!(f, ",{},", start.id())?;
+            write_str(f, start.name())?;
+            write!(f, ",{},{}", start.difficulty(), start.group_size())?;
+            match start.instance() {
+                Some(instance) => write!(f, ",{}", instance),
+                None => Ok(())
+            }
+        }
+        Payload::EncounterEnd(end) => {
+            write!(f, ",{},", end.id())?;
+            write_str(f, end.name())?;
+            write!(
+                f,
+                ",{},{},{}",
+                end.difficulty(),
+                end.group_size(),
+                end.success() as u8
+            )?;
+            match end.fight_time() {
+                Some(fight_time) => write!(f, ",{}", fight_time),
+                None => Ok(())
+            }
+        }
+        Payload::Unknown(_) | Payload::Raw(_) => write_raw(f, payload.raw().unwrap_or_default())
+    }
+}
+
+/// Writes an event as a line of a log, created by `Event::display`.
+pub struct DisplayEvent<'a> {
+    event:  &'a Event,
+    units:  &'a UnitTable,
+    format: LogFormat
+}
+
+impl Event {
+    /// Write the event as a line of a log without a header, without the line
+    /// ending, like `4/14 11:50:16.122  SWING_DAMAGE,0xF130005E49000018,...`.
+    /// The units are looked up in the given table.
+    pub fn display<'a>(&'a self, units: &'a UnitTable) -> DisplayEvent<'a> {
+        self.display_with(units, LogFormat::Legacy)
+    }
+
+    /// Write the event as a line of a log of the given format.
+    pub fn display_with<'a>(&'a self, units: &'a UnitTable, format: LogFormat) -> DisplayEvent<'a> {
+        DisplayEvent {
+            event: self,
+            units,
+            format
+        }
+    }
+}
+
+impl fmt::Display for DisplayEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let event = self.event;
+        let typ = event.typ();
+        write!(
+            f,
+            "{}  {}",
+            event.time(self.units).format("%-m/%-d %H:%M:%S%.3f"),
+            typ
+        )?;
+
+        let (mut cols, mut none);
+        let extras: Extras = match event.extras() {
+            Some(extras) => {
+                cols = self.units.extras(extras);
+                &mut cols
+            }
+            None => {
+                none = iter::empty();
+                &mut none
+            }
+        };
+
+        // These events are not about units, and the fields of unknown events
+        // already contain them.
+        match typ {
+            EventType::CombatLogVersion
+            | EventType::EncounterStart
+            | EventType::EncounterEnd
+            | EventType::Unknown(_) => {}
+            _ => {
+                write_unit(f, event.source(self.units), self.format, extras)?;
+                write_unit(f, event.target(self.units), self.format, extras)?;
+            }
+        }
+
+        write_payload(f, &typ, event.payload(), self.format, extras)?;
+        // Whatever follows the payload in the log comes last.
+        for col in extras {
+            write!(f, ",{}", col)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write the events as the lines of a log, each followed by `LINE_ENDING`.
+/// Their units are looked up in the given table, and events following a
+/// `COMBAT_LOG_VERSION` event are written in the format it announces.
+pub fn write_events<W, E>(mut writer: W, units: &UnitTable, events: E) -> io::Result<()>
+where
+    W: Write,
+    E: IntoIterator,
+    E::Item: Borrow<Event>
+{
+    let mut format = LogFormat::Legacy;
+    for event in events {
+        let event = event.borrow();
+        write!(
+            writer,
+            "{}{}",
+            event.display_with(units, format),
+            LINE_ENDING
+        )?;
+        if let Some(header) = event.payload().format() {
+            format = header;
+        }
+    }
+
+    writer.flush()
+}
+
+impl Log {
+    /// Write all events of the log as the lines of a log. Rejected lines are
+    /// not written.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_events(writer, self.units(), self.events())
+    }
+}
+
+/// Writes all events of the log, each on its own line.
+impl fmt::Display for Log {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut format = LogFormat::Legacy;
+        for event in self.events() {
+            write!(
+                f,
+                "{}{}",
+                event.display_with(self.units(), format),
+                LINE_ENDING
+            )?;
+            if let Some(header) = event.payload().format() {
+                format = header;
+            }
+        }
+
+        Ok(())
+    }
+}